@@ -0,0 +1,156 @@
+//! Allocation benchmark for the capture -> client fan-out path.
+//!
+//! Simulates a 48kHz stereo capture callback feeding several clients and
+//! counts heap allocations per second, once for the previous channel based
+//! fan-out (one `Vec<f32>` per client and callback) and once for the sample
+//! ring with reusable per-client buffers.
+//!
+//! run with `cargo bench --bench fanout`
+
+#[path = "../src/audio/format/wav.rs"]
+#[allow(dead_code)]
+mod wav;
+#[path = "../src/http.rs"]
+#[allow(dead_code)]
+mod http;
+#[path = "../src/audio/ring.rs"]
+#[allow(dead_code)]
+mod ring;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::{self, BufWriter},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::bounded;
+use ring::{ReadResult, SampleRing};
+
+/// frames per capture callback (10ms at 48kHz)
+const FRAMES: usize = 480;
+const CHANNELS: usize = 2;
+const CLIENTS: usize = 4;
+const CHUNK_SIZE: usize = 8192;
+const DURATION: Duration = Duration::from_secs(2);
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let channels = channel_fanout();
+    println!("channel fan-out: {:>10.0} allocations/s", channels);
+    let ring = ring_fanout();
+    println!("sample ring:     {:>10.0} allocations/s", ring);
+}
+
+/// the previous design: every callback clones the samples for every client
+fn channel_fanout() -> f64 {
+    let running = Arc::new(AtomicBool::new(true));
+    let mut senders = Vec::new();
+    let mut handles = Vec::new();
+    for _ in 0..CLIENTS {
+        let (s, r) = bounded::<Vec<f32>>(1);
+        senders.push(s);
+        handles.push(thread::spawn(move || {
+            let mut writer = BufWriter::with_capacity(CHUNK_SIZE * 4 + 16, io::sink());
+            let mut buffer = Vec::with_capacity(16384);
+            while let Ok(samples) = r.recv() {
+                wav::encode_samples(&samples, 16, &mut buffer);
+                http::write_chunk(&mut writer, &buffer).unwrap();
+            }
+        }));
+    }
+
+    let callback = vec![0.25f32; FRAMES * CHANNELS];
+    let rate = measure(&running, || {
+        for s in &senders {
+            let _ = s.try_send(callback.to_vec());
+        }
+    });
+
+    drop(senders);
+    handles.into_iter().for_each(|h| h.join().unwrap());
+    rate
+}
+
+/// the sample ring with a cursor and reusable buffers per client
+fn ring_fanout() -> f64 {
+    let ring = Arc::new(SampleRing::new(1 << 18));
+    let running = Arc::new(AtomicBool::new(true));
+    let mut handles = Vec::new();
+    for _ in 0..CLIENTS {
+        let ring = ring.clone();
+        let running = running.clone();
+        handles.push(thread::spawn(move || {
+            let mut writer = BufWriter::with_capacity(CHUNK_SIZE * 4 + 16, io::sink());
+            let mut reader = ring.reader();
+            let mut samples = vec![0f32; CHUNK_SIZE];
+            let mut buffer = Vec::with_capacity(CHUNK_SIZE * 4);
+            while running.load(Ordering::Relaxed) {
+                if !reader.wait(1, Duration::from_millis(10)) {
+                    continue;
+                }
+                if let ReadResult::Samples(n) = reader.read(&mut samples) {
+                    wav::encode_samples(&samples[..n], 16, &mut buffer);
+                    http::write_chunk(&mut writer, &buffer).unwrap();
+                }
+            }
+        }));
+    }
+
+    let callback = vec![0.25f32; FRAMES * CHANNELS];
+    let rate = measure(&running, || ring.push(callback.iter().copied()));
+
+    handles.into_iter().for_each(|h| h.join().unwrap());
+    rate
+}
+
+/// run the capture callback in real time and return the allocations per second
+fn measure<F: FnMut()>(running: &AtomicBool, mut callback: F) -> f64 {
+    let period = Duration::from_secs_f64(FRAMES as f64 / 48000.0);
+
+    // warm up, so one-time allocations of the clients are not counted
+    for _ in 0..10 {
+        callback();
+        thread::sleep(period);
+    }
+
+    let start = Instant::now();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let mut next = start;
+    while start.elapsed() < DURATION {
+        callback();
+        next += period;
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    let elapsed = start.elapsed();
+
+    running.store(false, Ordering::Relaxed);
+    allocations as f64 / elapsed.as_secs_f64()
+}
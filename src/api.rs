@@ -0,0 +1,370 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{IpAddr, SocketAddr, TcpStream},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio::{capture::capture_latency, level::ChannelLevel},
+    config::{gain_range, watch::select_profile, GAIN_RANGE_DB},
+    dsp::{
+        self,
+        delay::MAX_DELAY_MS,
+        eq::{EqSettings, PRESETS},
+        gain::{Volume, VolumeUpdate},
+    },
+    http::{write_response, Request},
+    renderer::{
+        events::{self, RendererState},
+        set_renderer_volume, sonos, start_group, Renderer, RendererError, SONOS_PORT,
+    },
+    stream::{latency::LatencyMode, sync::mean_position, StatsSnapshot},
+    CLIENTS, CONFIG, DELAYS, EQUALIZERS, LEVELS, RING, VOLUMES,
+};
+
+/// handle a request to the `/api` endpoints
+pub fn handle_request(mut stream: &TcpStream, request: &Request) -> io::Result<()> {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/api/stats") => json(&mut stream, &stats()),
+        ("GET", "/api/levels") => json(&mut stream, &levels()),
+        ("GET", "/api/profile") => json(&mut stream, &profile()),
+        ("PUT", "/api/profile") => {
+            let selection: ProfileSelection = match serde_json::from_slice(&request.body) {
+                Ok(selection) => selection,
+                Err(e) => return bad_request(&mut stream, &e.to_string()),
+            };
+            match select_profile(selection.profile.as_deref().unwrap_or_default()) {
+                Ok(()) => json(&mut stream, &profile()),
+                Err(e) => bad_request(&mut stream, &e.to_string()),
+            }
+        }
+        ("GET", "/api/volume") => json(&mut stream, &volumes()),
+        ("PUT", path) if path.starts_with("/api/volume") => {
+            let update: VolumeUpdate = match serde_json::from_slice(&request.body) {
+                Ok(update) => update,
+                Err(e) => return bad_request(&mut stream, &e.to_string()),
+            };
+            if update.gain_db.is_some_and(|db| !GAIN_RANGE_DB.contains(&db)) {
+                return bad_request(&mut stream, &format!("gain_db {}", gain_range()));
+            }
+            match path.strip_prefix("/api/volume").unwrap_or_default() {
+                "" => VOLUMES.set_global(VOLUMES.global(&CONFIG.read()).update(update)),
+                client => match client.trim_start_matches('/').parse::<IpAddr>() {
+                    Ok(ip) => {
+                        // the volume only changes once the renderer accepted it
+                        let volume = VOLUMES.client(&CONFIG.read(), ip).update(update);
+                        if let Err(e) = set_renderer_volume(ip, volume) {
+                            return bad_gateway(&mut stream, &e.to_string());
+                        }
+                        VOLUMES.set_client(ip, volume);
+                    }
+                    Err(_) => return bad_request(&mut stream, "expected /api/volume/<client ip>"),
+                },
+            }
+            dsp::settings_changed();
+            json(&mut stream, &volumes())
+        }
+        ("GET", "/api/eq") => json(&mut stream, &equalizers()),
+        ("PUT", path) if path.starts_with("/api/eq/") => {
+            let Ok(ip) = path["/api/eq/".len()..].parse::<IpAddr>() else {
+                return bad_request(&mut stream, "expected /api/eq/<client ip>");
+            };
+            let settings: EqSettings = match serde_json::from_slice(&request.body) {
+                Ok(settings) => settings,
+                Err(e) => return bad_request(&mut stream, &e.to_string()),
+            };
+            if let Err(e) = settings.validate() {
+                return bad_request(&mut stream, &e);
+            }
+            EQUALIZERS.set(ip, settings);
+            dsp::settings_changed();
+            json(&mut stream, &equalizers())
+        }
+        ("GET", "/api/delay") => json(&mut stream, &delays()),
+        ("PUT", path) if path.starts_with("/api/delay/") => {
+            let Ok(ip) = path["/api/delay/".len()..].parse::<IpAddr>() else {
+                return bad_request(&mut stream, "expected /api/delay/<client ip>");
+            };
+            let delay: DelayUpdate = match serde_json::from_slice(&request.body) {
+                Ok(delay) => delay,
+                Err(e) => return bad_request(&mut stream, &e.to_string()),
+            };
+            if delay.delay_ms > MAX_DELAY_MS {
+                return bad_request(&mut stream, &format!("delay_ms must be at most {MAX_DELAY_MS}"));
+            }
+            DELAYS.set(ip, delay.delay_ms);
+            dsp::settings_changed();
+            json(&mut stream, &delays())
+        }
+        ("GET", "/api/renderers") => json(&mut stream, &renderers()),
+        ("GET", "/api/multiroom") => json(&mut stream, &multiroom()),
+        ("POST", "/api/multiroom/start") => {
+            let renderers: Vec<Renderer> = CONFIG.read().renderers().map(Renderer::new).collect();
+            let errors: BTreeMap<String, String> = start_group(&renderers)
+                .into_iter()
+                .map(|(renderer, e)| (renderer.name.clone(), e.to_string()))
+                .collect();
+            if !errors.is_empty() {
+                let error = errors.iter().map(|(name, e)| format!("{name}: {e}")).collect::<Vec<_>>();
+                return bad_gateway(&mut stream, &error.join(", "));
+            }
+            json(&mut stream, &multiroom())
+        }
+        ("GET", "/api/sonos/groups") => {
+            let Some(ip) = CONFIG.read().renderers().next().map(|renderer| renderer.ip_addr) else {
+                return bad_request(&mut stream, "no renderer configured");
+            };
+            match sonos::zone_groups(SocketAddr::new(ip, SONOS_PORT)) {
+                Ok(groups) => json(&mut stream, &groups),
+                Err(e) => bad_gateway(&mut stream, &e.to_string()),
+            }
+        }
+        ("PUT", path) if path.starts_with("/api/sonos/groups/") => {
+            let Ok(speaker) = path["/api/sonos/groups/".len()..].parse::<IpAddr>() else {
+                return bad_request(&mut stream, "expected /api/sonos/groups/<speaker ip>");
+            };
+            let update: GroupUpdate = match serde_json::from_slice(&request.body) {
+                Ok(update) => update,
+                Err(e) => return bad_request(&mut stream, &e.to_string()),
+            };
+            match join_group(speaker, update.coordinator) {
+                Ok(groups) => json(&mut stream, &groups),
+                Err(e) => bad_request(&mut stream, &e.to_string()),
+            }
+        }
+        _ => write_response(&mut stream, "404 Not Found", "text/plain", b"not found"),
+    }
+}
+
+#[derive(Serialize)]
+struct Stats {
+    latency: LatencyMode,
+    /// expected delay from capture to the network, without the renderer's buffer
+    expected_latency_ms: u128,
+    clients: BTreeMap<String, StatsSnapshot>,
+}
+
+fn stats() -> Stats {
+    let (profile, limiter) = {
+        let config = CONFIG.read();
+        (config.app.latency_profile(), config.audio.limiter)
+    };
+    let clients = CLIENTS
+        .read()
+        .iter()
+        .map(|(ip, stats)| (ip.to_string(), stats.snapshot()))
+        .collect();
+    Stats {
+        latency: profile.mode,
+        expected_latency_ms: profile.expected_latency(capture_latency(), limiter).as_millis(),
+        clients,
+    }
+}
+
+/// the latest levels of the captured audio
+#[derive(Serialize)]
+struct Levels {
+    channels: Vec<ChannelLevel>,
+}
+
+fn levels() -> Levels {
+    Levels {
+        channels: LEVELS
+            .latest()
+            .map(|levels| levels.channel_levels())
+            .unwrap_or_default(),
+    }
+}
+
+/// the global volume and the volume of every connected client
+#[derive(Serialize)]
+struct Volumes {
+    global: Volume,
+    clients: BTreeMap<String, Volume>,
+}
+
+fn volumes() -> Volumes {
+    let config = CONFIG.read();
+    let clients = CLIENTS
+        .read()
+        .keys()
+        .map(|ip| (ip.to_string(), VOLUMES.client(&config, *ip)))
+        .collect();
+    Volumes {
+        global: VOLUMES.global(&config),
+        clients,
+    }
+}
+
+/// the equalizer presets and the settings of every connected client
+#[derive(Serialize)]
+struct Equalizers {
+    presets: Vec<&'static str>,
+    clients: BTreeMap<String, EqSettings>,
+}
+
+fn equalizers() -> Equalizers {
+    let config = CONFIG.read();
+    let clients = CLIENTS
+        .read()
+        .keys()
+        .map(|ip| (ip.to_string(), EQUALIZERS.settings(&config, *ip)))
+        .collect();
+    Equalizers {
+        presets: PRESETS.iter().map(|(name, _)| *name).collect(),
+        clients,
+    }
+}
+
+/// body of `PUT /api/delay/<client ip>`
+#[derive(Deserialize)]
+struct DelayUpdate {
+    delay_ms: usize,
+}
+
+/// the delay of every connected client in milliseconds
+fn delays() -> BTreeMap<String, usize> {
+    let config = CONFIG.read();
+    CLIENTS
+        .read()
+        .keys()
+        .map(|ip| (ip.to_string(), DELAYS.delay_ms(&config, *ip)))
+        .collect()
+}
+
+/// a configured renderer and its state, as reported by its events
+#[derive(Serialize)]
+struct RendererInfo {
+    name: String,
+    ip: IpAddr,
+    connected: bool,
+    /// `null` while sonar is not subscribed to the renderer's events
+    state: Option<RendererState>,
+}
+
+fn renderers() -> Vec<RendererInfo> {
+    let config = CONFIG.read();
+    let clients = CLIENTS.read();
+    config
+        .renderers()
+        .map(|renderer| RendererInfo {
+            name: renderer.name.clone(),
+            ip: renderer.ip_addr,
+            connected: clients.contains_key(&renderer.ip_addr),
+            state: events::state(renderer.ip_addr),
+        })
+        .collect()
+}
+
+/// the renderers of the multiroom group and how far they are apart
+#[derive(Serialize)]
+struct Multiroom {
+    enabled: bool,
+    members: Vec<Member>,
+}
+
+#[derive(Serialize)]
+struct Member {
+    name: String,
+    ip: IpAddr,
+    connected: bool,
+    /// how far the member's stream is ahead of the group's average
+    offset_ms: Option<f64>,
+}
+
+fn multiroom() -> Multiroom {
+    let config = CONFIG.read();
+    let clients = CLIENTS.read();
+    let ips: Vec<IpAddr> = config.renderers().map(|renderer| renderer.ip_addr).collect();
+    let mean = mean_position(&ips, &clients);
+    let samples_per_ms = RING.ms_to_samples(1000).max(1) as f64 / 1000.0;
+
+    let members = config
+        .renderers()
+        .map(|renderer| {
+            let stats = clients.get(&renderer.ip_addr).map(|stats| stats.snapshot());
+            // members that haven't sent anything yet have no position
+            let position = stats.as_ref().filter(|stats| stats.sent_samples > 0).map(|stats| stats.position);
+            Member {
+                name: renderer.name.clone(),
+                ip: renderer.ip_addr,
+                connected: stats.is_some(),
+                offset_ms: position
+                    .zip(mean)
+                    .map(|(position, mean)| (position as f64 - mean) / samples_per_ms),
+            }
+        })
+        .collect();
+    Multiroom {
+        enabled: config.multiroom.enabled,
+        members,
+    }
+}
+
+/// body of `PUT /api/sonos/groups/<speaker ip>`, `null` makes the speaker leave its group
+#[derive(Deserialize)]
+struct GroupUpdate {
+    coordinator: Option<IpAddr>,
+}
+
+/// add a speaker to the group of another one, or take it out of its group
+fn join_group(speaker: IpAddr, coordinator: Option<IpAddr>) -> Result<Vec<sonos::ZoneGroup>, RendererError> {
+    let addr = SocketAddr::new(speaker, SONOS_PORT);
+    match coordinator {
+        Some(coordinator) => {
+            let groups = sonos::zone_groups(addr)?;
+            let uuid = groups
+                .iter()
+                .find_map(|group| group.member(coordinator))
+                .map(|member| member.uuid.clone())
+                .ok_or(RendererError::UnknownSpeaker(coordinator))?;
+            sonos::join(speaker, &uuid)?;
+        }
+        None => sonos::leave(speaker)?,
+    }
+    sonos::zone_groups(addr)
+}
+
+/// the active profile and the profiles of the config file
+#[derive(Serialize)]
+struct Profiles {
+    active: Option<String>,
+    available: Vec<String>,
+}
+
+/// body of `PUT /api/profile`, `null` disables the active profile
+#[derive(Deserialize)]
+struct ProfileSelection {
+    profile: Option<String>,
+}
+
+fn profile() -> Profiles {
+    let config = CONFIG.read();
+    Profiles {
+        active: config.profile.clone(),
+        available: config.profiles.keys().cloned().collect(),
+    }
+}
+
+#[derive(Serialize)]
+struct ApiError<'a> {
+    error: &'a str,
+}
+
+fn bad_request(stream: &mut &TcpStream, error: &str) -> io::Result<()> {
+    let body = serde_json::to_vec_pretty(&ApiError { error })?;
+    write_response(stream, "400 Bad Request", "application/json", &body)
+}
+
+/// a renderer failed or could not be reached
+fn bad_gateway(stream: &mut &TcpStream, error: &str) -> io::Result<()> {
+    let body = serde_json::to_vec_pretty(&ApiError { error })?;
+    write_response(stream, "502 Bad Gateway", "application/json", &body)
+}
+
+fn json<T: Serialize>(stream: &mut &TcpStream, value: &T) -> io::Result<()> {
+    let body = serde_json::to_vec_pretty(value)?;
+    write_response(stream, "200 OK", "application/json", &body)
+}
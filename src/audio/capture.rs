@@ -1,11 +1,28 @@
-use cpal::{traits::{DeviceTrait, StreamTrait}, Stream};
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use cpal::{
+    traits::{DeviceTrait, StreamTrait},
+    BufferSize, BuildStreamError, SampleFormat, Stream, StreamConfig, SupportedBufferSize,
+};
 use dasp_sample::{Sample, ToSample};
 use log::{debug, error, info};
 
-use crate::CLIENTS;
+use crate::{CONFIG, RING, SILENCE_DETECTOR};
 
 use super::devices::Device;
 
+/// smallest capture buffer requested in low latency mode, devices report
+/// minimums of a few frames that can't be captured without overruns
+const MIN_BUFFER_FRAMES: u32 = 128;
+/// assumed capture latency when the device uses its default buffer size
+const DEFAULT_CAPTURE_LATENCY: Duration = Duration::from_millis(10);
+
+/// buffer size of the running capture stream in frames, `0` if the default is used
+static CAPTURE_BUFFER_FRAMES: AtomicU32 = AtomicU32::new(0);
+
 pub fn start_audio_capture(audio_output_device: &Device) -> Stream {
     debug!("Try capturing system audio");
     match capture_output_audio(audio_output_device) {
@@ -34,49 +51,85 @@ pub fn capture_output_audio(device_wrap: &Device) -> Option<Stream> {
         .default_config_any()
         .expect("No default stream config found");
     debug!("Default audio {audio_cfg:?}");
-    let mut f32_samples: Vec<f32> = Vec::with_capacity(16384);
-    match audio_cfg.sample_format() {
-        cpal::SampleFormat::F32 => match device.build_input_stream(
-            &audio_cfg.config(),
-            move |data, _: &_| wave_reader_f32(data),
-            capture_err_fn,
-            None,
-        ) {
-            Ok(stream) => Some(stream),
-            Err(e) => {
-                error!("Error capturing f32 audio stream: {e}");
-                None
-            }
-        },
-        cpal::SampleFormat::I16 => {
-            match device.build_input_stream(
-                &audio_cfg.config(),
-                move |data, _: &_| wave_reader::<i16>(data, &mut f32_samples),
-                capture_err_fn,
-                None,
-            ) {
-                Ok(stream) => Some(stream),
-                Err(e) => {
-                    error!("Error capturing i16 audio stream: {e}");
-                    None
-                }
-            }
+    RING.configure(audio_cfg.sample_rate().0, audio_cfg.channels());
+
+    let (profile, limiter) = {
+        let config = CONFIG.read();
+        (config.app.latency_profile(), config.audio.limiter)
+    };
+    let mut stream_cfg = audio_cfg.config();
+    if profile.min_capture_buffer {
+        if let SupportedBufferSize::Range { min, max } = audio_cfg.buffer_size() {
+            stream_cfg.buffer_size = BufferSize::Fixed((*min).max(MIN_BUFFER_FRAMES).min(*max));
+        }
+    }
+
+    let stream = match build_input_stream(device, audio_cfg.sample_format(), &stream_cfg) {
+        Err(e) if stream_cfg.buffer_size != BufferSize::Default => {
+            // not every backend accepts fixed buffer sizes (e.g. WASAPI loopback)
+            info!("Could not use the minimal capture buffer ({e}), using the default");
+            stream_cfg.buffer_size = BufferSize::Default;
+            build_input_stream(device, audio_cfg.sample_format(), &stream_cfg)
+        }
+        result => result,
+    };
+
+    match stream {
+        Ok(stream) => {
+            let frames = match stream_cfg.buffer_size {
+                BufferSize::Fixed(frames) => frames,
+                BufferSize::Default => 0,
+            };
+            CAPTURE_BUFFER_FRAMES.store(frames, Ordering::Relaxed);
+            info!(
+                "Latency mode '{}', expected latency {}ms (plus renderer buffer)",
+                profile.mode,
+                profile.expected_latency(capture_latency(), limiter).as_millis()
+            );
+            Some(stream)
         }
-        cpal::SampleFormat::U16 => {
-            match device.build_input_stream(
-                &audio_cfg.config(),
-                move |data, _: &_| wave_reader::<u16>(data, &mut f32_samples),
-                capture_err_fn,
-                None,
-            ) {
-                Ok(stream) => Some(stream),
-                Err(e) => {
-                    error!("Error capturing u16 audio stream: {e}");
-                    None
-                }
-            }
+        Err(e) => {
+            error!("Error capturing {} audio stream: {e}", audio_cfg.sample_format());
+            None
         }
-        _ => None,
+    }
+}
+
+/// duration of the capture device's buffer
+///
+/// falls back to an estimate if the device uses its default buffer size
+pub fn capture_latency() -> Duration {
+    match CAPTURE_BUFFER_FRAMES.load(Ordering::Relaxed) {
+        0 => DEFAULT_CAPTURE_LATENCY,
+        frames => Duration::from_secs_f64(frames as f64 / RING.sample_rate() as f64),
+    }
+}
+
+fn build_input_stream(
+    device: &cpal::Device,
+    sample_format: SampleFormat,
+    config: &StreamConfig,
+) -> Result<Stream, BuildStreamError> {
+    match sample_format {
+        SampleFormat::F32 => device.build_input_stream(
+            config,
+            move |data, _: &_| wave_reader::<f32>(data),
+            capture_err_fn,
+            None,
+        ),
+        SampleFormat::I16 => device.build_input_stream(
+            config,
+            move |data, _: &_| wave_reader::<i16>(data),
+            capture_err_fn,
+            None,
+        ),
+        SampleFormat::U16 => device.build_input_stream(
+            config,
+            move |data, _: &_| wave_reader::<u16>(data),
+            capture_err_fn,
+            None,
+        ),
+        _ => Err(BuildStreamError::StreamConfigNotSupported),
     }
 }
 
@@ -87,22 +140,14 @@ fn capture_err_fn(err: cpal::StreamError) {
 
 /// wave_reader - the captured audio input stream reader
 ///
-/// writes the captured samples to all registered clients in the
-/// CLIENTS ChannnelStream hashmap
-/// also feeds the RMS monitor channel if the RMS option is set
-fn wave_reader<T>(samples: &[T], f32_samples: &mut Vec<f32>)
+/// converts the captured samples to f32 and appends them to the shared
+/// sample ring, from where every client reads at its own pace.
+/// also feeds the silence detector
+/// this runs on the real-time audio thread, so it must never block or allocate
+fn wave_reader<T>(samples: &[T])
 where
     T: Sample + ToSample<f32>,
 {
-    f32_samples.clear();
-    f32_samples.extend(samples.iter().map(|x: &T| T::to_sample::<f32>(*x)));
-    for (_, s) in CLIENTS.read().iter() {
-        s.send(f32_samples.clone()).unwrap();
-    }
-}
-
-fn wave_reader_f32(samples: &[f32]) {
-    for (_, s) in CLIENTS.read().iter() {
-        let _ = s.try_send(samples.to_vec());
-    }
+    RING.push(samples.iter().map(|x: &T| T::to_sample::<f32>(*x)));
+    SILENCE_DETECTOR.feed(samples.iter().map(|x: &T| T::to_sample::<f32>(*x)));
 }
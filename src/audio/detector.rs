@@ -0,0 +1,57 @@
+use std::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// SilenceDetector - tracks when the captured audio was last above a threshold
+///
+/// The capture callback feeds every buffer into the detector, which compares
+/// the buffer's RMS level against the threshold. Only atomics are used, so it
+/// is safe to call from the real-time audio thread.
+pub struct SilenceDetector {
+    created: Instant,
+    /// mean square threshold as f32 bits
+    threshold: AtomicU32,
+    /// time of the last buffer above the threshold in milliseconds since `created`
+    last_sound: AtomicU64,
+}
+
+impl SilenceDetector {
+    pub fn new(threshold_db: f32) -> Self {
+        let detector = Self {
+            created: Instant::now(),
+            threshold: AtomicU32::new(0),
+            last_sound: AtomicU64::new(0),
+        };
+        detector.set_threshold(threshold_db);
+        detector
+    }
+
+    /// Sets the RMS level in dBFS below which audio is considered silent.
+    pub fn set_threshold(&self, threshold_db: f32) {
+        let amplitude = 10f32.powf(threshold_db / 20.0);
+        self.threshold
+            .store((amplitude * amplitude).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Feeds a buffer of captured samples into the detector.
+    pub fn feed<I>(&self, samples: I)
+    where
+        I: IntoIterator<Item = f32>,
+    {
+        let (sum, n) = samples
+            .into_iter()
+            .fold((0f32, 0usize), |(sum, n), s| (sum + s * s, n + 1));
+        let threshold = f32::from_bits(self.threshold.load(Ordering::Relaxed));
+        if n > 0 && sum / n as f32 > threshold {
+            self.last_sound
+                .store(self.created.elapsed().as_millis() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// how long the captured audio has been silent
+    pub fn silent_for(&self) -> Duration {
+        let last_sound = Duration::from_millis(self.last_sound.load(Ordering::Relaxed));
+        self.created.elapsed().saturating_sub(last_sound)
+    }
+}
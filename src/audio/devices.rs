@@ -1,4 +1,4 @@
-use cpal::traits::DeviceTrait;
+use cpal::traits::{DeviceTrait, HostTrait};
 use log::debug;
 
 use super::WavData;
@@ -65,3 +65,26 @@ impl AsRef<cpal::Device> for Device {
         }
     }
 }
+
+/// find an output device of the default host by its name
+pub fn find_output_device(name: &str) -> Option<Device> {
+    cpal::default_host()
+        .output_devices()
+        .ok()?
+        .find(|device| device.name().map(|n| n == name).unwrap_or(false))
+        .map(Device::Output)
+}
+
+/// find a device of the default host by its name
+///
+/// output devices are preferred, input devices are searched too because
+/// some backends expose loopback sources (e.g. PulseAudio's "Monitor of ...")
+/// as inputs. `index` picks between devices with the same name
+pub fn find_device(name: &str, index: usize) -> Option<Device> {
+    let host = cpal::default_host();
+    let matches = |device: &cpal::Device| device.name().map(|n| n == name).unwrap_or(false);
+
+    let outputs = host.output_devices().ok()?.filter(matches).map(Device::Output);
+    let inputs = host.input_devices().ok()?.filter(matches).map(Device::Input);
+    outputs.chain(inputs).nth(index)
+}
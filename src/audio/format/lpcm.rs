@@ -0,0 +1,36 @@
+use dasp_sample::Sample;
+
+/// encode f32 samples as big endian PCM with the given bit depth (`audio/L16`, `audio/L24`)
+///
+/// the stream has no header, the renderer takes the sample rate and the
+/// channels from the mime type. the output buffer is cleared and reused
+pub fn encode_samples(samples: &[f32], bits_per_sample: u16, out: &mut Vec<u8>) {
+    out.clear();
+    match bits_per_sample {
+        24 => {
+            for &sample in samples {
+                let sample = i32::from_sample(sample) >> 8;
+                out.extend_from_slice(&sample.to_be_bytes()[1..]);
+            }
+        }
+        _ => {
+            for &sample in samples {
+                out.extend_from_slice(&i16::from_sample(sample).to_be_bytes());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_are_big_endian() {
+        let mut out = Vec::new();
+        encode_samples(&[0.5, -1.0], 16, &mut out);
+        assert_eq!(out, [0x40, 0x00, 0x80, 0x00]);
+        encode_samples(&[0.5, -1.0], 24, &mut out);
+        assert_eq!(out, [0x40, 0x00, 0x00, 0x80, 0x00, 0x00]);
+    }
+}
@@ -1,3 +1,4 @@
+pub mod lpcm;
 pub mod wav;
 
 use serde::{Deserialize, Serialize};
@@ -12,8 +13,11 @@ pub enum StreamingState {
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub enum StreamingFormat {
+    #[serde(alias = "lpcm", alias = "LPCM")]
     Lpcm,
+    #[serde(alias = "wav", alias = "WAV")]
     Wav,
+    #[serde(alias = "flac", alias = "FLAC")]
     Flac,
 }
 
@@ -26,3 +30,14 @@ impl fmt::Display for StreamingFormat {
         }
     }
 }
+
+impl StreamingFormat {
+    /// the mime type of the stream, LPCM carries its parameters in it
+    pub fn mime_type(self, sample_rate: u32, channels: u16, bits_per_sample: u16) -> String {
+        match self {
+            StreamingFormat::Lpcm => format!("audio/L{bits_per_sample};rate={sample_rate};channels={channels}"),
+            StreamingFormat::Wav => "audio/wav".to_string(),
+            StreamingFormat::Flac => "audio/flac".to_string(),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use dasp_sample::Sample;
 use log::debug;
 
 /// create an "infinite size" wav hdr
@@ -10,7 +11,7 @@ pub fn create_header(sample_rate: u32, bits_per_sample: u16) -> [u8; 44] {
     let block_align: u16 = channels * bytes_per_sample;
     let byte_rate: u32 = sample_rate * block_align as u32;
     hdr[0..4].copy_from_slice(b"RIFF"); // ChunkId, little endian WAV
-    let subchunksize: u32 = std::u32::MAX; // "infinite" data chunksize signal value
+    let subchunksize: u32 = u32::MAX; // "infinite" data chunksize signal value
     let chunksize: u32 = subchunksize; // "infinite" RIFF chunksize signal value
     hdr[4..8].copy_from_slice(&chunksize.to_le_bytes()); // ChunkSize
     hdr[8..12].copy_from_slice(b"WAVE"); // File Format
@@ -27,3 +28,29 @@ pub fn create_header(sample_rate: u32, bits_per_sample: u16) -> [u8; 44] {
     debug!("WAV Header (l={}): \r\n{:02x?}", hdr.len(), hdr);
     hdr
 }
+
+/// encode f32 samples as little endian PCM with the given bit depth
+///
+/// the output buffer is cleared and reused, so no allocation happens
+/// as long as it has enough capacity (4 bytes per sample)
+pub fn encode_samples(samples: &[f32], bits_per_sample: u16, out: &mut Vec<u8>) {
+    out.clear();
+    match bits_per_sample {
+        24 => {
+            for &sample in samples {
+                let sample = i32::from_sample(sample) >> 8;
+                out.extend_from_slice(&sample.to_le_bytes()[..3]);
+            }
+        }
+        32 => {
+            for &sample in samples {
+                out.extend_from_slice(&i32::from_sample(sample).to_le_bytes());
+            }
+        }
+        _ => {
+            for &sample in samples {
+                out.extend_from_slice(&i16::from_sample(sample).to_le_bytes());
+            }
+        }
+    }
+}
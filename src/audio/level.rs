@@ -0,0 +1,186 @@
+use std::{
+    io::{self, Write},
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+
+use super::ring::{ReadResult, SampleRing};
+
+/// maximum number of channels the meter reports
+pub const MAX_CHANNELS: usize = 8;
+/// length of the measuring window
+const WINDOW_MS: usize = 50;
+/// lowest reported level
+const FLOOR_DB: f32 = -100.0;
+
+/// levels of one measuring window in dBFS
+#[derive(Debug, Clone, Copy)]
+pub struct Levels {
+    pub channels: usize,
+    pub rms: [f32; MAX_CHANNELS],
+    pub peak: [f32; MAX_CHANNELS],
+}
+
+/// the level of a single channel, as reported by the api
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ChannelLevel {
+    pub rms_db: f32,
+    pub peak_db: f32,
+}
+
+impl Levels {
+    pub fn channel_levels(&self) -> Vec<ChannelLevel> {
+        (0..self.channels)
+            .map(|c| ChannelLevel {
+                rms_db: self.rms[c],
+                peak_db: self.peak[c],
+            })
+            .collect()
+    }
+}
+
+/// LevelMonitor - measures per-channel RMS and peak levels of the captured audio
+///
+/// reads the sample ring like a client would, so the real-time capture callback
+/// is not burdened with it. every window is published to all subscribers and
+/// kept as the latest measurement for the api
+pub struct LevelMonitor {
+    latest: RwLock<Option<Levels>>,
+    subscribers: Mutex<Vec<Sender<Levels>>>,
+}
+
+impl LevelMonitor {
+    pub fn new() -> Self {
+        Self {
+            latest: RwLock::new(None),
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// the most recent measurement
+    pub fn latest(&self) -> Option<Levels> {
+        *self.latest.read()
+    }
+
+    /// Returns a channel receiving every measurement.
+    ///
+    /// Measurements are dropped if the receiver does not keep up.
+    pub fn subscribe(&self) -> Receiver<Levels> {
+        let (s, r) = bounded(16);
+        self.subscribers.lock().push(s);
+        r
+    }
+
+    fn publish(&self, levels: Levels) {
+        *self.latest.write() = Some(levels);
+        // drop the subscribers that went away
+        self.subscribers
+            .lock()
+            .retain(|s| !matches!(s.try_send(levels), Err(e) if e.is_disconnected()));
+    }
+
+    /// measure the audio in the ring, never returns
+    pub fn run(&self, ring: &SampleRing) {
+        let mut reader = ring.reader();
+        let mut samples = Vec::new();
+        loop {
+            let channels = (ring.channels() as usize).max(1);
+            let window = ring.ms_to_samples(WINDOW_MS) as usize;
+            samples.resize(window, 0.0);
+
+            if !reader.wait(window as u64, Duration::from_secs(1)) {
+                continue;
+            }
+            if let ReadResult::Samples(n) = reader.read(&mut samples) {
+                self.publish(measure(&samples[..n], channels));
+            }
+        }
+    }
+}
+
+impl Default for LevelMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// compute the levels of a window of interleaved samples
+///
+/// only the first [MAX_CHANNELS] channels are measured
+fn measure(samples: &[f32], channels: usize) -> Levels {
+    let mut sum = [0f32; MAX_CHANNELS];
+    let mut peak = [0f32; MAX_CHANNELS];
+    for frame in samples.chunks_exact(channels) {
+        for (c, &sample) in frame.iter().take(MAX_CHANNELS).enumerate() {
+            sum[c] += sample * sample;
+            peak[c] = peak[c].max(sample.abs());
+        }
+    }
+
+    let frames = (samples.len() / channels).max(1) as f32;
+    let channels = channels.min(MAX_CHANNELS);
+    let mut levels = Levels {
+        channels,
+        rms: [FLOOR_DB; MAX_CHANNELS],
+        peak: [FLOOR_DB; MAX_CHANNELS],
+    };
+    for c in 0..channels {
+        levels.rms[c] = to_db((sum[c] / frames).sqrt());
+        levels.peak[c] = to_db(peak[c]);
+    }
+    levels
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    (20.0 * amplitude.log10()).max(FLOOR_DB)
+}
+
+/// run_vu_meter - print a simple vu meter of the captured audio to the terminal
+///
+/// meant for debugging capture issues, never returns
+pub fn run_vu_meter(monitor: &LevelMonitor) {
+    const WIDTH: usize = 30;
+    const RANGE_DB: f32 = 60.0;
+
+    let levels = monitor.subscribe();
+    let mut line = String::new();
+    for levels in levels.iter() {
+        line.clear();
+        for c in 0..levels.channels {
+            let rms = ((levels.rms[c] + RANGE_DB) / RANGE_DB).clamp(0.0, 1.0);
+            let peak = ((levels.peak[c] + RANGE_DB) / RANGE_DB).clamp(0.0, 1.0);
+            let rms = (rms * WIDTH as f32) as usize;
+            let peak = ((peak * WIDTH as f32) as usize).min(WIDTH - 1);
+            line.push_str(&format!("{c}["));
+            for i in 0..WIDTH {
+                line.push(match i {
+                    i if i < rms => '#',
+                    i if i == peak => '|',
+                    _ => ' ',
+                });
+            }
+            line.push_str(&format!("] {:6.1} dB  ", levels.rms[c]));
+        }
+        print!("\r{line}");
+        let _ = io::stdout().flush();
+    }
+}
+
+/// start the level monitor (and optionally the vu meter) on background threads
+pub fn start_level_monitor_thread(monitor: &'static LevelMonitor, ring: &'static SampleRing, vu_meter: bool) {
+    thread::Builder::new()
+        .name("level_monitor".into())
+        .spawn(move || monitor.run(ring))
+        .unwrap();
+
+    if vu_meter {
+        thread::Builder::new()
+            .name("vu_meter".into())
+            .spawn(move || run_vu_meter(monitor))
+            .unwrap();
+    }
+}
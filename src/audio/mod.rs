@@ -1,6 +1,9 @@
 pub mod capture;
+pub mod detector;
 pub mod devices;
 pub mod format;
+pub mod level;
+pub mod ring;
 pub mod silence;
 
 /// some audio config info
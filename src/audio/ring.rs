@@ -0,0 +1,209 @@
+use std::{
+    sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// how often a waiting reader polls the ring for new samples
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// SampleRing - a lock-free single producer / multi consumer sample buffer
+///
+/// The capture callback is the only writer. It never blocks and never
+/// allocates: samples are stored as `f32` bits in preallocated atomic slots
+/// and published by bumping the monotonic write position.
+///
+/// Every reader owns a [RingReader] with its own cursor. A reader that falls
+/// too far behind (e.g. a stalled client) drops the oldest samples and
+/// continues with the most recent audio instead of holding up the writer.
+pub struct SampleRing {
+    slots: Box<[AtomicU32]>,
+    mask: u64,
+    write_pos: AtomicU64,
+    sample_rate: AtomicU32,
+    channels: AtomicU16,
+    created: Instant,
+    /// time of the last write in milliseconds since `created`
+    last_write: AtomicU64,
+}
+
+/// the result of a [RingReader::read] call
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ReadResult {
+    /// `n` samples were copied into the output buffer
+    Samples(usize),
+    /// the reader fell behind and skipped `n` samples (drop-oldest)
+    Overrun(u64),
+}
+
+impl SampleRing {
+    /// Creates a ring holding `capacity` samples, rounded up to a power of two.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.next_power_of_two();
+        let slots = (0..capacity).map(|_| AtomicU32::new(0)).collect();
+        Self {
+            slots,
+            mask: capacity as u64 - 1,
+            write_pos: AtomicU64::new(0),
+            sample_rate: AtomicU32::new(48000),
+            channels: AtomicU16::new(2),
+            created: Instant::now(),
+            last_write: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the format of the samples written by the capture callback.
+    pub fn configure(&self, sample_rate: u32, channels: u16) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+        self.channels.store(channels, Ordering::Relaxed);
+    }
+
+    /// sample rate of the captured audio
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    /// number of interleaved channels of the captured audio
+    pub fn channels(&self) -> u16 {
+        self.channels.load(Ordering::Relaxed)
+    }
+
+    /// converts a duration in milliseconds to a number of samples (all channels)
+    pub fn ms_to_samples(&self, ms: usize) -> u64 {
+        let frames = self.sample_rate() as u64 * ms as u64 / 1000;
+        frames * self.channels() as u64
+    }
+
+    /// total number of samples the ring can hold
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// number of samples written since the ring was created
+    pub fn write_pos(&self) -> u64 {
+        self.write_pos.load(Ordering::Acquire)
+    }
+
+    /// how far a reader may lag behind before it drops samples
+    ///
+    /// a quarter of the ring is kept as a guard zone so the writer can never
+    /// overwrite samples a reader is currently copying
+    pub fn max_lag(&self) -> u64 {
+        (self.capacity() - self.capacity() / 4) as u64
+    }
+
+    /// Appends samples to the ring.
+    ///
+    /// Must only be called from a single thread (the capture callback).
+    pub fn push<I>(&self, samples: I)
+    where
+        I: IntoIterator<Item = f32>,
+    {
+        let mut pos = self.write_pos.load(Ordering::Relaxed);
+        for sample in samples {
+            self.slots[(pos & self.mask) as usize].store(sample.to_bits(), Ordering::Relaxed);
+            pos += 1;
+        }
+        self.write_pos.store(pos, Ordering::Release);
+        self.last_write
+            .store(self.created.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// time since samples were last written to the ring
+    pub fn since_last_write(&self) -> Duration {
+        let last_write = Duration::from_millis(self.last_write.load(Ordering::Relaxed));
+        self.created.elapsed().saturating_sub(last_write)
+    }
+
+    /// Creates a reader positioned at the current end of the ring.
+    pub fn reader(&self) -> RingReader<'_> {
+        RingReader {
+            ring: self,
+            pos: self.write_pos(),
+        }
+    }
+
+    /// Creates a reader positioned `n` samples before `pos`.
+    ///
+    /// The reader is moved forward if the ring does not hold that much history.
+    pub fn reader_before(&self, pos: u64, n: u64) -> RingReader<'_> {
+        let oldest = self.write_pos().saturating_sub(self.max_lag() / 2);
+        let channels = self.channels() as u64;
+        let start = pos.saturating_sub(n).max(oldest);
+        RingReader {
+            ring: self,
+            // keep the reader aligned to whole frames
+            pos: start - start % channels,
+        }
+    }
+}
+
+/// RingReader - a cursor into a [SampleRing] owned by a single consumer
+pub struct RingReader<'a> {
+    ring: &'a SampleRing,
+    pos: u64,
+}
+
+impl<'a> RingReader<'a> {
+    /// number of samples ready to be read
+    pub fn available(&self) -> u64 {
+        self.ring.write_pos() - self.pos
+    }
+
+    /// position of the next sample this reader will return
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Blocks until at least `min` samples are available or the timeout elapsed.
+    ///
+    /// Returns `true` if enough samples are available.
+    pub fn wait(&self, min: u64, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.available() < min.max(1) {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        true
+    }
+
+    /// Copies up to `out.len()` samples into `out` and advances the cursor.
+    ///
+    /// If the reader lags more than [SampleRing::max_lag] samples behind,
+    /// the oldest samples are dropped and [ReadResult::Overrun] is returned.
+    pub fn read(&mut self, out: &mut [f32]) -> ReadResult {
+        let write_pos = self.ring.write_pos();
+        if write_pos - self.pos > self.ring.max_lag() {
+            return self.skip_to_newest(write_pos);
+        }
+
+        let n = out.len().min((write_pos - self.pos) as usize);
+        for (i, sample) in out[..n].iter_mut().enumerate() {
+            let slot = &self.ring.slots[((self.pos + i as u64) & self.ring.mask) as usize];
+            *sample = f32::from_bits(slot.load(Ordering::Relaxed));
+        }
+
+        // the writer might have lapped us while we were copying
+        let write_pos = self.ring.write_pos();
+        if write_pos - self.pos > self.ring.max_lag() {
+            return self.skip_to_newest(write_pos);
+        }
+
+        self.pos += n as u64;
+        ReadResult::Samples(n)
+    }
+
+    /// Drops the next `n` samples (at most all available samples).
+    pub fn skip(&mut self, n: u64) {
+        self.pos += n.min(self.available());
+    }
+
+    /// drop everything that has not been read yet
+    fn skip_to_newest(&mut self, write_pos: u64) -> ReadResult {
+        let skipped = write_pos - self.pos;
+        self.pos = write_pos;
+        ReadResult::Overrun(skipped)
+    }
+}
@@ -1,54 +1,120 @@
-use std::time::Duration;
+use std::thread::{self, JoinHandle};
 
 use cpal::{
     traits::{DeviceTrait, StreamTrait},
-    SampleFormat,
+    SampleFormat, Stream,
 };
+use crossbeam_channel::{bounded, Sender};
 use dasp_sample::Sample;
+use log::{debug, error, info};
 
-use super::devices::Device;
+use super::devices::{find_output_device, Device};
 
-// fn start_silence_injector_thread(_audio_output_device: Device) {
-//     if let Some(true) = CONFIG.read().inject_silence {
-//         let _ = thread::Builder::new()
-//             .name("silence_injector".into())
-//             .stack_size(4 * 1024 * 1024)
-//             .spawn(move || run_silence_injector(&audio_output_device))
-//             .unwrap();
-//     }
-// }
+/// SilenceInjector - plays silence on the captured output device
+///
+/// Some backends (e.g. WASAPI loopback) don't deliver any capture callbacks
+/// while nothing is playing, which starves the clients. Playing silence keeps
+/// the loopback stream running.
+///
+/// The injector runs on its own thread (cpal streams can't be moved between
+/// threads) and is stopped when the handle is dropped.
+pub struct SilenceInjector {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SilenceInjector {
+    /// Starts injecting silence into the given output device.
+    pub fn start(device: &Device) -> Result<Self, String> {
+        // cpal devices can't be cloned, the thread looks the device up by name
+        let name = match device {
+            Device::Output(device) => device.name().map_err(|e| e.to_string())?,
+            Device::Input(_) => return Err("not an output device".to_string()),
+        };
+
+        let (stop_tx, stop_rx) = bounded::<()>(0);
+        let (started_tx, started_rx) = bounded(1);
+        let thread = thread::Builder::new()
+            .name("silence_injector".into())
+            .spawn(move || {
+                let stream = match find_output_device(&name)
+                    .ok_or_else(|| format!("output device '{name}' not found"))
+                    .and_then(|device| run_silence_injector(&device))
+                {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        let _ = started_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = started_tx.send(Ok(()));
+                // blocks until the handle is dropped
+                let _ = stop_rx.recv();
+                drop(stream);
+                debug!("silence injector stopped");
+            })
+            .map_err(|e| e.to_string())?;
+
+        match started_rx.recv() {
+            Ok(Ok(())) => {
+                info!("Injecting silence into the output device");
+                Ok(Self {
+                    stop: Some(stop_tx),
+                    thread: Some(thread),
+                })
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err("silence injector thread panicked".to_string()),
+        }
+    }
+
+    /// Stops the injector and waits for its thread to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        // dropping the sender wakes up the injector thread
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SilenceInjector {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
 
 /// inject silence into the audio stream to
 /// solve problems with Sonos when pusing audio
-pub fn run_silence_injector(device: &Device) {
+///
+/// the returned stream plays silence until it is dropped
+pub fn run_silence_injector(device: &Device) -> Result<Stream, String> {
     let config = device
         .default_config_any()
-        .expect("Error while querying stream configs for the silence injector");
+        .map_err(|e| format!("Error while querying stream configs for the silence injector: {e}"))?;
 
     let sample_format = config.sample_format();
-    let err_fn = |err| eprintln!("an error occurred on the output audio stream: {err}");
+    let err_fn = |err| error!("an error occurred on the output audio stream: {err}");
     let config = config.into();
 
     let device = device.as_ref();
     let stream = match sample_format {
-        SampleFormat::F32 => device
-            .build_output_stream(&config, write_silence::<f32>, err_fn, None)
-            .unwrap(),
-        SampleFormat::I16 => device
-            .build_output_stream(&config, write_silence::<i16>, err_fn, None)
-            .unwrap(),
-        SampleFormat::U16 => device
-            .build_output_stream(&config, write_silence::<u16>, err_fn, None)
-            .unwrap(),
-        format => panic!("Unsupported sample format: {format:?}"),
-    };
+        SampleFormat::F32 => device.build_output_stream(&config, write_silence::<f32>, err_fn, None),
+        SampleFormat::I16 => device.build_output_stream(&config, write_silence::<i16>, err_fn, None),
+        SampleFormat::U16 => device.build_output_stream(&config, write_silence::<u16>, err_fn, None),
+        format => return Err(format!("Unsupported sample format: {format:?}")),
+    }
+    .map_err(|e| e.to_string())?;
+
     stream
         .play()
-        .expect("Unable to inject silence into the output stream");
+        .map_err(|e| format!("Unable to inject silence into the output stream: {e}"))?;
 
-    loop {
-        std::thread::sleep(Duration::from_secs(1));
-    }
+    Ok(stream)
 }
 
 fn write_silence<T: Sample>(data: &mut [T], _: &cpal::OutputCallbackInfo) {
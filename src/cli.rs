@@ -0,0 +1,143 @@
+use std::{env, path::PathBuf};
+
+use crate::{
+    config::{Override, CONFIG_ENV},
+    APP_NAME, APP_VERSION,
+};
+
+/// shorthand flags for frequently used config fields
+const FIELD_FLAGS: [(&str, &str); 12] = [
+    ("--profile", "profile"),
+    ("--port", "server.port"),
+    ("--bind", "server.network"),
+    ("--workers", "server.workers"),
+    ("--format", "audio.format"),
+    ("--bits", "audio.bits_per_sample"),
+    ("--device", "device.name"),
+    ("--renderer", "renderers.0.name"),
+    ("--renderer-ip", "renderers.0.ip_addr"),
+    ("--log-level", "app.log_level"),
+    ("--latency", "app.latency"),
+    ("--buffer", "app.buffer_ms"),
+];
+
+/// subcommands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// print the effective config
+    ConfigShow,
+}
+
+/// command line arguments
+#[derive(Debug, Default)]
+pub struct Args {
+    /// path of the config file
+    pub config: Option<PathBuf>,
+    /// don't create or rewrite any files
+    pub read_only: bool,
+    /// show a vu meter of the captured audio
+    pub vu_meter: bool,
+    pub command: Option<Command>,
+    /// config fields set on the command line
+    pub overrides: Vec<Override>,
+}
+
+impl Args {
+    /// parse the arguments of the current process
+    pub fn parse() -> Result<Self, String> {
+        Self::parse_from(env::args().skip(1))
+    }
+
+    pub fn parse_from<I>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // support both `--flag value` and `--flag=value`
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("missing value for '{flag}'"))
+            };
+            match flag.as_str() {
+                "-c" | "--config" => parsed.config = Some(PathBuf::from(value()?)),
+                "--read-only" => parsed.read_only = true,
+                "--vu" => parsed.vu_meter = true,
+                "-h" | "--help" => {
+                    println!("{}", usage());
+                    std::process::exit(0);
+                }
+                "-V" | "--version" => {
+                    println!("{APP_NAME} {APP_VERSION}");
+                    std::process::exit(0);
+                }
+                "--set" => {
+                    let value = value()?;
+                    let (key, value) = value
+                        .split_once('=')
+                        .ok_or_else(|| format!("expected '--set <key>=<value>', got '{value}'"))?;
+                    parsed.overrides.push(Override::new(key, value, &flag));
+                }
+                "config" if parsed.command.is_none() => match args.next().as_deref() {
+                    Some("show") => parsed.command = Some(Command::ConfigShow),
+                    other => return Err(format!("unknown config command '{}'", other.unwrap_or_default())),
+                },
+                _ => {
+                    // `--server.port 5902` sets any field, the shorthands cover the common ones
+                    let key = match FIELD_FLAGS.iter().find(|(f, _)| *f == flag) {
+                        Some((_, key)) => key.to_string(),
+                        None if flag.starts_with("--") && flag.contains('.') => flag[2..].to_string(),
+                        None => return Err(format!("unknown argument '{flag}'")),
+                    };
+                    parsed.overrides.push(Override::new(&key, &value()?, &flag));
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+pub fn usage() -> String {
+    format!(
+        concat!(
+            "Usage: {name} [OPTIONS] [COMMAND]\n",
+            "\n",
+            "Commands:\n",
+            "  config show               print the effective config and exit\n",
+            "\n",
+            "Options:\n",
+            "  -c, --config <PATH>       use this config file (also ${env})\n",
+            "      --read-only           don't create or rewrite any files\n",
+            "      --vu                  show a vu meter of the captured audio\n",
+            "      --profile <NAME>      use the settings of [profiles.<NAME>]\n",
+            "      --port <PORT>         server.port\n",
+            "      --bind <IP>           server.network\n",
+            "      --workers <N>         server.workers\n",
+            "      --format <FORMAT>     audio.format (wav, lpcm)\n",
+            "      --bits <BITS>         audio.bits_per_sample\n",
+            "      --device <NAME>       device.name\n",
+            "      --renderer <NAME>     renderers.0.name\n",
+            "      --renderer-ip <IP>    renderers.0.ip_addr\n",
+            "      --log-level <LEVEL>   app.log_level\n",
+            "      --latency <MODE>      app.latency (low, balanced, safe)\n",
+            "      --buffer <MS>         app.buffer_ms\n",
+            "      --<KEY> <VALUE>       set any field, e.g. --app.auto_stop 300\n",
+            "      --set <KEY>=<VALUE>   set any field, e.g. --set server.port=5902\n",
+            "  -h, --help                print this help\n",
+            "  -V, --version             print the version\n",
+            "\n",
+            "Every field can also be set with a SONAR_<SECTION>_<FIELD> environment\n",
+            "variable, e.g. SONAR_SERVER_PORT=5902. Flags take precedence over the\n",
+            "environment, which takes precedence over the config file.\n",
+        ),
+        name = APP_NAME,
+        env = CONFIG_ENV
+    )
+}
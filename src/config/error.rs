@@ -0,0 +1,202 @@
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// errors while loading the config file
+#[derive(Debug)]
+pub enum ConfigError {
+    /// the file could not be read
+    Io { path: PathBuf, source: std::io::Error },
+    /// the file is not valid toml or does not match the config structure
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        field: Option<String>,
+        message: String,
+    },
+    /// a value is out of its allowed range
+    Invalid { field: &'static str, reason: String },
+    /// an environment variable or command line flag has an invalid value
+    Override {
+        origin: String,
+        key: String,
+        message: String,
+    },
+    /// the selected profile does not exist or has invalid values
+    Profile { name: String, message: String },
+}
+
+impl ConfigError {
+    pub(super) fn invalid(field: &'static str, reason: &str) -> Self {
+        ConfigError::Invalid {
+            field,
+            reason: reason.to_string(),
+        }
+    }
+
+    pub(super) fn parse(path: &Path, text: &str, error: &toml::de::Error) -> Self {
+        let offset = error.span().map(|span| span.start).unwrap_or_default();
+        let (line, column) = line_column(text, offset);
+        ConfigError::Parse {
+            path: path.to_path_buf(),
+            line,
+            column,
+            field: field_at(text, offset, error.message()),
+            message: error.message().replace('\n', ", "),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "could not read '{}': {source}", path.display())
+            }
+            ConfigError::Parse {
+                path,
+                line,
+                column,
+                field,
+                message,
+            } => {
+                write!(f, "{}:{line}:{column}: ", path.display())?;
+                if let Some(field) = field {
+                    write!(f, "invalid `{field}`: ")?;
+                }
+                write!(f, "{message}")
+            }
+            ConfigError::Invalid { field, reason } => {
+                write!(f, "invalid `{field}`: {reason}")
+            }
+            ConfigError::Override {
+                origin,
+                key,
+                message,
+            } => write!(f, "invalid `{key}` (from {origin}): {message}"),
+            ConfigError::Profile { name, message } => write!(f, "profile '{name}': {message}"),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// 1-based line and column of a byte offset
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    (line, column)
+}
+
+/// the dotted name of the field at a byte offset, e.g. `server.port`
+///
+/// toml errors only carry a span, so the field is derived from the
+/// enclosing table header and the key on the offending line
+fn field_at(text: &str, offset: usize, message: &str) -> Option<String> {
+    let offset = offset.min(text.len());
+    let line_start = text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = text[line_start..].lines().next().unwrap_or_default().trim();
+
+    let table = text[..line_start]
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|l| l.starts_with('['))
+        .map(|l| l.trim_matches(|c| c == '[' || c == ']').trim().to_string());
+
+    let (table, key) = if line.starts_with('[') {
+        // errors like missing fields point to the table header
+        let table = line.trim_matches(|c| c == '[' || c == ']').trim().to_string();
+        let key = message.split('`').nth(1).filter(|_| message.starts_with("missing field"));
+        (Some(table), key.map(str::to_string))
+    } else {
+        let key = line.split_once('=').map(|(key, _)| key.trim().to_string());
+        (table, key)
+    };
+
+    match (table, key) {
+        (Some(table), Some(key)) => Some(format!("{table}.{key}")),
+        (None, Some(key)) => Some(key),
+        (Some(table), None) => Some(table),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn line_column_of_offset() {
+        let text = "a = 1\nb = 2\n";
+        assert_eq!(line_column(text, 0), (1, 1));
+        assert_eq!(line_column(text, 4), (1, 5));
+        assert_eq!(line_column(text, 6), (2, 1));
+        assert_eq!(line_column(text, 10), (2, 5));
+        // past the end of the text
+        assert_eq!(line_column(text, 100), (3, 1));
+    }
+
+    #[test]
+    fn line_column_counts_characters() {
+        let text = "name = \"Küche\"\nport = x";
+        let offset = text.find('x').unwrap();
+        assert_eq!(line_column(text, offset), (2, 8));
+        let offset = text.find('"').unwrap() + "\"Kü".len();
+        assert_eq!(line_column(text, offset), (1, 11));
+    }
+
+    #[test]
+    fn field_at_key_in_table() {
+        let text = "[app]\nlatency = \"low\"\n\n[server]\nport = \"x\"\n";
+        let offset = text.find("\"x\"").unwrap();
+        assert_eq!(field_at(text, offset, "invalid type"), Some("server.port".to_string()));
+        let offset = text.find("latency").unwrap();
+        assert_eq!(field_at(text, offset, "invalid type"), Some("app.latency".to_string()));
+    }
+
+    #[test]
+    fn field_at_top_level_key() {
+        let text = "profile = 1\n";
+        assert_eq!(field_at(text, 10, "invalid type"), Some("profile".to_string()));
+    }
+
+    #[test]
+    fn field_at_table_header_for_missing_field() {
+        let text = "[server]\nport = 1\n\n[device]\nindex = 1\n";
+        let offset = text.find("[device]").unwrap();
+        assert_eq!(
+            field_at(text, offset, "missing field `name`"),
+            Some("device.name".to_string())
+        );
+        assert_eq!(field_at(text, offset, "invalid type"), Some("device".to_string()));
+    }
+
+    #[test]
+    fn parse_error_points_to_field() {
+        let text = "[app]\nlatency = \"low\"\n\n[server]\nport = \"high\"\n";
+        let error = toml::from_str::<Config>(text).unwrap_err();
+        let error = ConfigError::parse(Path::new("config.toml"), text, &error);
+        let ConfigError::Parse {
+            line, column, field, ..
+        } = &error
+        else {
+            panic!("expected a parse error, got {error:?}");
+        };
+        assert_eq!((*line, *column), (5, 8));
+        assert_eq!(field.as_deref(), Some("server.port"));
+        assert!(error.to_string().starts_with("config.toml:5:8: invalid `server.port`: "));
+    }
+}
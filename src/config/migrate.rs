@@ -0,0 +1,225 @@
+use std::{fs, path::Path};
+
+use log::{info, warn};
+use toml_edit::{value, Array, ArrayOfTables, Document, Item, Table, Value};
+
+/// version of the config file layout written by this version of sonar
+pub const CONFIG_VERSION: u32 = 2;
+
+/// a migration from one config version to the next
+type Migration = fn(&mut Document);
+
+/// MIGRATIONS[n] migrates a config from version `n` to `n + 1`
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [v0_to_v1, v1_to_v2];
+
+/// migrate - bring an older config file up to [CONFIG_VERSION]
+///
+/// the migrations work on the toml document, so comments and formatting of
+/// the user's file are preserved. the migrated file is written back if
+/// `write_back` is set, otherwise only the returned text is migrated.
+/// invalid toml is returned unchanged, the caller reports the error
+pub fn migrate(path: &Path, text: String, write_back: bool) -> String {
+    let mut doc = match text.parse::<Document>() {
+        Ok(doc) => doc,
+        Err(_) => return text,
+    };
+
+    let version = doc
+        .get("config_version")
+        .and_then(|v| v.as_integer())
+        .unwrap_or(0)
+        .max(0) as u32;
+    if version > CONFIG_VERSION {
+        warn!(
+            "'{}' was written by a newer version of sonar (config version {version})",
+            path.display()
+        );
+        return text;
+    }
+    if version == CONFIG_VERSION {
+        return text;
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut doc);
+    }
+    doc["config_version"] = value(CONFIG_VERSION as i64);
+
+    let migrated = doc.to_string();
+    if write_back {
+        match fs::write(path, &migrated) {
+            Ok(()) => info!(
+                "migrated '{}' from config version {version} to {CONFIG_VERSION}",
+                path.display()
+            ),
+            Err(e) => warn!("could not write migrated config '{}': {e}", path.display()),
+        }
+    }
+    migrated
+}
+
+/// version 1 introduced the `config_version` field, missing
+/// fields are filled in with their defaults from now on
+fn v0_to_v1(_doc: &mut Document) {}
+
+/// version 2 replaced the single `[renderer]` by the first entry of
+/// `[[renderers]]`, profiles contain a list of renderers as well
+fn v1_to_v2(doc: &mut Document) {
+    let mut renderers = ArrayOfTables::new();
+    if let Some(renderer) = doc.remove("renderer").and_then(into_table) {
+        renderers.push(renderer);
+    }
+
+    // the other renderers were only used while multiroom was enabled
+    let multiroom = doc
+        .get("multiroom")
+        .and_then(|multiroom| multiroom.get("enabled"))
+        .and_then(Item::as_bool)
+        .unwrap_or(false);
+    for mut renderer in doc.remove("renderers").map(into_tables).unwrap_or_default() {
+        if !multiroom {
+            renderer["enabled"] = value(false);
+        }
+        renderers.push(renderer);
+    }
+    if !renderers.is_empty() {
+        doc["renderers"] = Item::ArrayOfTables(renderers);
+    }
+
+    let Some(profiles) = doc.get_mut("profiles").and_then(Item::as_table_like_mut) else {
+        return;
+    };
+    for (_, profile) in profiles.iter_mut() {
+        let Some(profile) = profile.as_table_like_mut() else {
+            continue;
+        };
+        if let Some(renderer) = profile.remove("renderer").and_then(into_table) {
+            let mut renderers = Array::new();
+            renderers.push(renderer.into_inline_table());
+            profile.insert("renderers", value(renderers));
+        }
+    }
+}
+
+fn into_table(item: Item) -> Option<Table> {
+    match item {
+        Item::Table(table) => Some(table),
+        Item::Value(Value::InlineTable(table)) => Some(table.into_table()),
+        _ => None,
+    }
+}
+
+fn into_tables(item: Item) -> Vec<Table> {
+    match item {
+        Item::ArrayOfTables(tables) => tables.into_iter().collect(),
+        Item::Value(Value::Array(array)) => array
+            .into_iter()
+            .filter_map(|value| into_table(Item::Value(value)))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    const V1: &str = r#"config_version = 1
+
+# the main speaker
+[renderer]
+name = "Living Room"
+ip_addr = "192.168.1.20"
+delay_ms = 40
+eq_preset = "bass-cut"
+
+[[renderer.eq]]
+type = "peaking"
+freq = 250.0
+gain_db = -3.0
+q = 1.4
+
+[multiroom]
+enabled = false
+
+[[renderers]]
+name = "Kitchen"
+ip_addr = "192.168.1.22"
+
+[profiles.office]
+audio = { bits_per_sample = 16 }
+renderer = { name = "Office", ip_addr = "192.168.1.21" }
+"#;
+
+    fn migrated(text: &str) -> (String, Config) {
+        let text = migrate(Path::new("config.toml"), text.to_string(), false);
+        let config = toml::from_str(&text).unwrap();
+        (text, config)
+    }
+
+    #[test]
+    fn v1_to_v2_moves_renderers_into_list() {
+        let (text, config) = migrated(V1);
+        assert_eq!(config.config_version, CONFIG_VERSION);
+        assert!(text.contains("# the main speaker"));
+        assert!(!text.contains("[renderer]"));
+
+        let names: Vec<&str> = config.renderers.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["Living Room", "Kitchen"]);
+        let living_room = &config.renderers[0];
+        assert!(living_room.enabled);
+        assert_eq!(living_room.delay_ms, 40);
+        assert_eq!(living_room.eq_preset.as_deref(), Some("bass-cut"));
+        assert_eq!(living_room.eq.len(), 1);
+        assert_eq!(living_room.eq[0].freq, 250.0);
+    }
+
+    #[test]
+    fn v1_to_v2_keeps_multiroom_state() {
+        // the other renderers were not used while multiroom was disabled
+        let (_, config) = migrated(V1);
+        assert!(!config.renderers[1].enabled);
+
+        let (_, config) = migrated(&V1.replace("enabled = false", "enabled = true"));
+        assert!(config.renderers[1].enabled);
+        assert!(config.multiroom.enabled);
+    }
+
+    #[test]
+    fn v1_to_v2_converts_profile_renderer() {
+        let (_, config) = migrated(V1);
+        let profile = &config.profiles["office"];
+        let renderers = profile.renderers.as_ref().unwrap();
+        assert_eq!(renderers.len(), 1);
+        assert_eq!(renderers[0]["name"].as_str(), Some("Office"));
+        assert_eq!(renderers[0]["ip_addr"].as_str(), Some("192.168.1.21"));
+        assert_eq!(profile.audio.as_ref().unwrap()["bits_per_sample"].as_integer(), Some(16));
+    }
+
+    #[test]
+    fn v1_to_v2_without_renderers() {
+        let (text, config) = migrated("config_version = 1\n\n[server]\nport = 5902\n");
+        assert!(config.renderers.is_empty());
+        assert!(!text.contains("renderers"));
+        assert_eq!(config.server.port, 5902);
+    }
+
+    #[test]
+    fn v0_gets_the_current_version() {
+        let text = "# the main speaker\n[server]\nport = 5902\n".to_string();
+        let text = migrate(Path::new("config.toml"), text, false);
+        assert!(text.contains("# the main speaker"));
+        let config: Config = toml::from_str(&text).unwrap();
+        assert_eq!(config.config_version, CONFIG_VERSION);
+        assert_eq!(config.server.port, 5902);
+    }
+
+    #[test]
+    fn current_and_newer_versions_are_unchanged() {
+        let current = format!("config_version = {CONFIG_VERSION}\n[renderer]\nname = \"a\"\n");
+        assert_eq!(migrate(Path::new("config.toml"), current.clone(), false), current);
+        let newer = format!("config_version = {}\n", CONFIG_VERSION + 1);
+        assert_eq!(migrate(Path::new("config.toml"), newer.clone(), false), newer);
+    }
+}
@@ -0,0 +1,496 @@
+mod error;
+mod migrate;
+pub mod overrides;
+pub mod profiles;
+pub mod watch;
+
+pub use error::ConfigError;
+pub use migrate::CONFIG_VERSION;
+pub use overrides::Override;
+pub use profiles::Profile;
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use toml::{from_str, Table};
+
+use crate::{
+    audio::format::StreamingFormat,
+    dsp::{
+        delay::MAX_DELAY_MS,
+        eq::{Band, EqSettings},
+    },
+    stream::{
+        latency::{LatencyMode, LatencyProfile},
+        preroll::PrerollMode,
+    },
+    APP_NAME,
+};
+
+/// environment variable overriding the config file location
+pub const CONFIG_ENV: &str = "SONAR_CONFIG";
+/// name of the config file in the config directory
+const CONFIG_FILE: &str = "config.toml";
+/// allowed software gain
+pub const GAIN_RANGE_DB: std::ops::RangeInclusive<f32> = -96.0..=12.0;
+
+/// whether sonar can stream a format with this bit depth
+fn check_format(format: StreamingFormat, bits_per_sample: u16) -> Result<(), String> {
+    match format {
+        StreamingFormat::Wav => Ok(()),
+        StreamingFormat::Lpcm if bits_per_sample == 32 => Err("Lpcm supports 16 or 24 bits per sample".to_string()),
+        StreamingFormat::Lpcm => Ok(()),
+        StreamingFormat::Flac => Err("Flac is not supported, use Wav or Lpcm".to_string()),
+    }
+}
+
+/// the allowed software gain, for error messages
+pub fn gain_range() -> String {
+    format!("must be between {} and {} dB", GAIN_RANGE_DB.start(), GAIN_RANGE_DB.end())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// layout version of the config file, older files are migrated on load
+    pub config_version: u32,
+    /// name of the active profile
+    pub profile: Option<String>,
+    pub app: AppConfig,
+    pub server: ServerConfig,
+    pub device: Option<DeviceConfig>,
+    pub audio: AudioConfig,
+    pub multiroom: MultiroomConfig,
+    /// named sets of audio, renderer and device settings
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// the renderers sonar controls, `[[renderers]]`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub renderers: Vec<RendererConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub log_level: LevelFilter,
+    pub auto_reconnect: bool,
+    pub inject_silence: bool,
+    /// milliseconds without captured audio before silence is streamed instead
+    pub capture_timeout: usize,
+    /// trade-off between delay and robustness of the stream
+    pub latency: LatencyMode,
+    /// RMS level in dBFS below which the captured audio counts as silence
+    pub silence_threshold: f32,
+    /// seconds of silence after which the renderer is stopped, `0` disables this
+    pub auto_stop: u64,
+    /// target fill level of each client's audio buffer in milliseconds,
+    /// defaults to the buffer size of the latency mode
+    pub buffer_ms: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub network: IpAddr,
+    pub port: u16,
+    pub workers: u16,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    pub name: String,
+    #[serde(default)]
+    pub index: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RendererConfig {
+    pub name: String,
+    pub ip_addr: IpAddr,
+    /// disabled renderers are kept in the config but not controlled
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    /// overrides `audio.format` for this renderer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<StreamingFormat>,
+    /// overrides `audio.bits_per_sample` for this renderer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bits_per_sample: Option<u16>,
+    /// set the renderer's own volume instead of changing the samples
+    #[serde(default)]
+    pub volume_sync: bool,
+    /// audio sent in a burst when the renderer connects
+    #[serde(default)]
+    pub preroll: PrerollMode,
+    /// length of the pre-roll burst in milliseconds
+    #[serde(default)]
+    pub preroll_ms: usize,
+    /// software volume of this renderer in dB, on top of `audio.gain_db`
+    #[serde(default)]
+    pub gain_db: f32,
+    #[serde(default)]
+    pub mute: bool,
+    /// delay of the audio sent to this renderer in milliseconds,
+    /// to line it up with other rooms or a video
+    #[serde(default)]
+    pub delay_ms: usize,
+    /// stream to the coordinator of the speaker's Sonos group
+    /// instead of the speaker itself
+    #[serde(default)]
+    pub group_coordinator: bool,
+    /// speakers added to the Sonos group of this renderer when it starts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub group_members: Vec<IpAddr>,
+    /// equalizer preset, applied before the bands in `eq`
+    #[serde(default)]
+    pub eq_preset: Option<String>,
+    /// equalizer bands, `[[renderers.eq]]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub eq: Vec<Band>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+/// play all enabled renderers in sync
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MultiroomConfig {
+    pub enabled: bool,
+    /// how long the first renderer of the group waits for the others
+    /// before streaming starts, in milliseconds
+    pub start_timeout_ms: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub format: StreamingFormat,
+    pub bits_per_sample: u16,
+    /// software volume of all clients in dB, for renderers without volume control
+    pub gain_db: f32,
+    pub mute: bool,
+    /// limit the true peaks of the processed audio, delays the stream by 5ms
+    pub limiter: bool,
+    /// highest true peak in dBTP
+    pub limiter_ceiling_db: f32,
+    /// time for the gain to recover after a peak in milliseconds
+    pub limiter_release_ms: usize,
+    /// slowly adjust the volume towards `loudness_target_lufs` (EBU R128)
+    pub loudness_normalize: bool,
+    pub loudness_target_lufs: f32,
+    /// most the normalizer raises the volume of quiet audio in dB
+    pub loudness_max_gain_db: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            config_version: CONFIG_VERSION,
+            profile: None,
+            app: AppConfig::default(),
+            server: ServerConfig::default(),
+            device: None,
+            audio: AudioConfig::default(),
+            multiroom: MultiroomConfig::default(),
+            profiles: BTreeMap::new(),
+            renderers: Vec::new(),
+        }
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            log_level: LevelFilter::Info,
+            auto_reconnect: true,
+            inject_silence: true,
+            capture_timeout: 250,
+            silence_threshold: -60.0,
+            auto_stop: 0,
+            latency: LatencyMode::Balanced,
+            buffer_ms: None,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            network: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            port: 5901,
+            workers: 8,
+        }
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            format: StreamingFormat::Wav,
+            bits_per_sample: 16,
+            gain_db: 0.0,
+            mute: false,
+            limiter: true,
+            limiter_ceiling_db: -1.0,
+            limiter_release_ms: 100,
+            loudness_normalize: false,
+            loudness_target_lufs: -18.0,
+            loudness_max_gain_db: 12.0,
+        }
+    }
+}
+
+impl Default for MultiroomConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start_timeout_ms: 3000,
+        }
+    }
+}
+
+impl AppConfig {
+    /// the log level to use, debug builds always log debug messages
+    pub fn log_level(&self) -> LevelFilter {
+        if cfg!(debug_assertions) {
+            LevelFilter::Debug
+        } else {
+            self.log_level
+        }
+    }
+
+    /// the buffer sizes of the latency mode, with the configured buffer size applied
+    pub fn latency_profile(&self) -> LatencyProfile {
+        let mut profile = self.latency.profile();
+        if let Some(buffer_ms) = self.buffer_ms {
+            profile.buffer_ms = buffer_ms;
+            profile.prefill_ms = profile.prefill_ms.min(buffer_ms);
+        }
+        profile
+    }
+}
+
+impl RendererConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !GAIN_RANGE_DB.contains(&self.gain_db) {
+            return Err(ConfigError::invalid(
+                "renderers.gain_db",
+                &format!("'{}': {}, got {}", self.name, gain_range(), self.gain_db),
+            ));
+        }
+        if self.delay_ms > MAX_DELAY_MS {
+            return Err(ConfigError::invalid(
+                "renderers.delay_ms",
+                &format!("'{}': must be at most {MAX_DELAY_MS}ms, got {}", self.name, self.delay_ms),
+            ));
+        }
+        if self.bits_per_sample.is_some_and(|bits| ![16, 24, 32].contains(&bits)) {
+            return Err(ConfigError::invalid(
+                "renderers.bits_per_sample",
+                &format!("'{}': must be 16, 24 or 32", self.name),
+            ));
+        }
+        let eq = EqSettings {
+            preset: self.eq_preset.clone(),
+            bands: self.eq.clone(),
+        };
+        eq.validate()
+            .map_err(|reason| ConfigError::invalid("renderers.eq", &format!("'{}': {reason}", self.name)))
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Finds the config file.
+    ///
+    /// In this order: the `--config` flag, `$SONAR_CONFIG`,
+    /// `$XDG_CONFIG_HOME/sonar/config.toml`, the legacy `~/.sonar/config.toml`
+    /// if it exists, and `config.toml` in the platform's config directory.
+    pub fn locate(flag: Option<&Path>) -> PathBuf {
+        if let Some(path) = flag {
+            return path.to_path_buf();
+        }
+        if let Some(path) = env::var_os(CONFIG_ENV).filter(|p| !p.is_empty()) {
+            return PathBuf::from(path);
+        }
+        if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|p| !p.is_empty()) {
+            return PathBuf::from(dir).join(APP_NAME).join(CONFIG_FILE);
+        }
+
+        let home_dir = dirs::home_dir().unwrap_or_default();
+        let legacy = home_dir.join(".".to_string() + APP_NAME).join(CONFIG_FILE);
+        if legacy.exists() {
+            return legacy;
+        }
+        dirs::config_dir()
+            .unwrap_or(home_dir)
+            .join(APP_NAME)
+            .join(CONFIG_FILE)
+    }
+
+    /// Loads the config file, creating it with default values if it doesn't exist.
+    ///
+    /// Missing fields are filled in with their defaults, older files are migrated.
+    /// With `read_only` set no files are created or rewritten, a missing
+    /// config file means the defaults are used.
+    ///
+    /// The selected profile is merged into the file, then the overrides
+    /// (environment variables and command line flags) are applied in order.
+    pub fn load(path: &Path, read_only: bool, overrides: &[Override]) -> Result<Self, ConfigError> {
+        let text = match Self::read(path, read_only)? {
+            Some(text) => text,
+            None => Config::default().to_toml().unwrap_or_default(),
+        };
+
+        // check the file on its own first, to report errors with their location
+        let parse_error = |e: toml::de::Error| ConfigError::parse(path, &text, &e);
+        let _: Config = from_str(&text).map_err(parse_error)?;
+        let mut table: Table = from_str(&text).map_err(parse_error)?;
+
+        profiles::apply(&mut table, overrides)?;
+        let mut config = overrides::apply(table, overrides)?;
+        // an empty name selects no profile
+        config.profile = config.profile.filter(|name| !name.is_empty());
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// read and migrate the config file, `None` means the defaults should be used
+    fn read(path: &Path, read_only: bool) -> Result<Option<String>, ConfigError> {
+        if read_only && !path.exists() {
+            info!("'{}' does not exist, using default config", path.display());
+            return Ok(None);
+        }
+        if !read_only {
+            if let Err(e) = Self::check(path) {
+                // not being able to write the default config is no reason not to start
+                warn!("could not create '{}': {e}, using default config", path.display());
+                return Ok(None);
+            }
+        }
+
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Ok(Some(migrate::migrate(path, text, !read_only)))
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        Self::check(path)?;
+
+        fs::write(path, self.to_toml()?)
+    }
+
+    /// Checks the semantic constraints serde can't express.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.port == 0 {
+            return Err(ConfigError::invalid("server.port", "must be between 1 and 65535"));
+        }
+        if self.server.workers == 0 {
+            return Err(ConfigError::invalid("server.workers", "must be greater than 0"));
+        }
+        if ![16, 24, 32].contains(&self.audio.bits_per_sample) {
+            return Err(ConfigError::invalid("audio.bits_per_sample", "must be 16, 24 or 32"));
+        }
+        check_format(self.audio.format, self.audio.bits_per_sample)
+            .map_err(|reason| ConfigError::invalid("audio.format", &reason))?;
+        if !GAIN_RANGE_DB.contains(&self.audio.gain_db) {
+            return Err(ConfigError::invalid("audio.gain_db", &gain_range()));
+        }
+        if !(-20.0..=0.0).contains(&self.audio.limiter_ceiling_db) {
+            return Err(ConfigError::invalid("audio.limiter_ceiling_db", "must be between -20 and 0 dB"));
+        }
+        if self.audio.limiter_release_ms == 0 {
+            return Err(ConfigError::invalid("audio.limiter_release_ms", "must be greater than 0"));
+        }
+        if !(-40.0..=-5.0).contains(&self.audio.loudness_target_lufs) {
+            return Err(ConfigError::invalid("audio.loudness_target_lufs", "must be between -40 and -5 LUFS"));
+        }
+        if !(0.0..=24.0).contains(&self.audio.loudness_max_gain_db) {
+            return Err(ConfigError::invalid("audio.loudness_max_gain_db", "must be between 0 and 24 dB"));
+        }
+        for (i, renderer) in self.renderers.iter().enumerate() {
+            renderer.validate()?;
+            let format = renderer.format.unwrap_or(self.audio.format);
+            let bits_per_sample = renderer.bits_per_sample.unwrap_or(self.audio.bits_per_sample);
+            check_format(format, bits_per_sample).map_err(|reason| {
+                ConfigError::invalid("renderers.format", &format!("'{}': {reason}", renderer.name))
+            })?;
+            if self.renderers[..i].iter().any(|other| other.ip_addr == renderer.ip_addr) {
+                return Err(ConfigError::invalid(
+                    "renderers.ip_addr",
+                    &format!("'{}': {} is used by another renderer", renderer.name, renderer.ip_addr),
+                ));
+            }
+        }
+        if self.app.capture_timeout == 0 {
+            return Err(ConfigError::invalid("app.capture_timeout", "must be greater than 0"));
+        }
+        if self.app.buffer_ms == Some(0) {
+            return Err(ConfigError::invalid("app.buffer_ms", "must be greater than 0"));
+        }
+        if self.app.silence_threshold > 0.0 {
+            return Err(ConfigError::invalid("app.silence_threshold", "must be at most 0 dBFS"));
+        }
+        Ok(())
+    }
+
+    /// the enabled renderers
+    pub fn renderers(&self) -> impl Iterator<Item = &RendererConfig> {
+        self.renderers.iter().filter(|renderer| renderer.enabled)
+    }
+
+    /// the config of the renderer with this address
+    pub fn renderer_for(&self, ip: IpAddr) -> Option<&RendererConfig> {
+        self.renderers().find(|renderer| renderer.ip_addr == ip)
+    }
+
+    /// the format and bits per sample of the stream sent to a client
+    pub fn stream_format(&self, ip: IpAddr) -> (StreamingFormat, u16) {
+        let renderer = self.renderer_for(ip);
+        (
+            renderer.and_then(|renderer| renderer.format).unwrap_or(self.audio.format),
+            renderer
+                .and_then(|renderer| renderer.bits_per_sample)
+                .unwrap_or(self.audio.bits_per_sample),
+        )
+    }
+
+    /// the addresses of the multiroom group, empty if it is disabled
+    pub fn sync_group(&self) -> Vec<IpAddr> {
+        match self.multiroom.enabled {
+            true => self.renderers().map(|renderer| renderer.ip_addr).collect(),
+            false => Vec::new(),
+        }
+    }
+
+    pub fn to_toml(&self) -> std::io::Result<String> {
+        toml::to_string_pretty(self).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// create the config file with default values if it doesn't exist
+    fn check(config_file: &Path) -> std::io::Result<()> {
+        if let Some(config_dir) = config_file.parent().filter(|dir| !dir.exists()) {
+            // create config directory
+            fs::create_dir_all(config_dir)?;
+        }
+        if !config_file.exists() {
+            // create config with default values
+            fs::write(config_file, Config::default().to_toml()?)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,333 @@
+use std::env;
+
+use log::warn;
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use super::{Config, ConfigError, CONFIG_ENV};
+
+/// prefix of the environment variables overriding config fields
+const ENV_PREFIX: &str = "SONAR_";
+
+/// the tables of the config file, used to map environment variables to fields
+const SECTIONS: [&str; 5] = ["app", "server", "device", "audio", "multiroom"];
+/// the top level fields that can be set from the environment
+const TOP_LEVEL: [&str; 1] = ["profile"];
+/// the lists of tables, their entries are addressed by index
+const LISTS: [&str; 1] = ["renderers"];
+
+/// a config field set on the command line or in the environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Override {
+    /// dotted path of the field, e.g. `server.port`
+    pub key: String,
+    pub value: String,
+    /// where the override came from, e.g. `--port` or `SONAR_SERVER_PORT`
+    pub origin: String,
+}
+
+impl Override {
+    pub fn new(key: &str, value: &str, origin: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            value: value.to_string(),
+            origin: origin.to_string(),
+        }
+    }
+
+    fn error(&self, message: &str) -> ConfigError {
+        ConfigError::Override {
+            origin: self.origin.clone(),
+            key: self.key.clone(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// from_env - collect the overrides from `SONAR_<SECTION>_<FIELD>` environment variables
+///
+/// e.g. `SONAR_SERVER_PORT=5902` sets `server.port`, `SONAR_RENDERERS_0_NAME`
+/// sets `renderers.0.name` and `SONAR_PROFILE` the top level `profile`. other
+/// `SONAR_` variables, e.g. of other tools, are ignored with a warning
+pub fn from_env() -> Vec<Override> {
+    let mut overrides: Vec<_> = env::vars()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX) && name != CONFIG_ENV)
+        .filter_map(|(name, value)| match env_key(&name[ENV_PREFIX.len()..].to_lowercase()) {
+            Some(key) => Some(Override::new(&key, &value, &name)),
+            None => {
+                warn!("ignoring '{name}', it doesn't name a config field");
+                None
+            }
+        })
+        .collect();
+    // make the order independent of the environment
+    overrides.sort_by(|a, b| a.origin.cmp(&b.origin));
+    overrides
+}
+
+/// the dotted key of an environment variable without the prefix, e.g. `server_port`
+fn env_key(field: &str) -> Option<String> {
+    if TOP_LEVEL.contains(&field) {
+        return Some(field.to_string());
+    }
+    let (section, rest) = field.split_once('_').filter(|(_, rest)| !rest.is_empty())?;
+    if SECTIONS.contains(&section) {
+        return Some(format!("{section}.{rest}"));
+    }
+    let (index, rest) = rest.split_once('_').filter(|(_, rest)| !rest.is_empty())?;
+    match LISTS.contains(&section) && index.parse::<usize>().is_ok() {
+        true => Some(format!("{section}.{index}.{rest}")),
+        false => None,
+    }
+}
+
+/// apply - set the overridden fields in the parsed config file and build the config
+///
+/// every override is checked on its own, so errors point to the flag or
+/// variable that caused them. a later override may complete an entry,
+/// e.g. `--renderer` followed by `--renderer-ip`
+pub fn apply(mut table: Table, overrides: &[Override]) -> Result<Config, ConfigError> {
+    // the override after which the config became invalid
+    let mut culprit = None;
+    for o in overrides {
+        let value = parse_value(&o.value);
+        let mut typed = table.clone();
+        set(&mut typed, &o.key, value.clone()).map_err(|message| o.error(&message))?;
+        let error = match check(&typed) {
+            Ok(()) => {
+                table = typed;
+                culprit = None;
+                continue;
+            }
+            Err(error) => error,
+        };
+        // a value that looks like a number or boolean may be meant as text,
+        // e.g. `--device 1` for a device named "1"
+        if !value.is_str() {
+            let mut text = table.clone();
+            set(&mut text, &o.key, Value::String(o.value.clone())).map_err(|message| o.error(&message))?;
+            match check(&text) {
+                Ok(()) => {
+                    table = text;
+                    culprit = None;
+                    continue;
+                }
+                // the entry is still incomplete, but the text has the right type
+                Err(text_error) if is_type_error(&error) && !is_type_error(&text_error) => {
+                    table = text;
+                    culprit = culprit.or(Some(o));
+                    continue;
+                }
+                Err(_) => {}
+            }
+        }
+        table = typed;
+        culprit = culprit.or(Some(o));
+    }
+    Config::deserialize(Value::Table(table)).map_err(|e| match culprit {
+        Some(o) => o.error(e.message()),
+        None => ConfigError::Invalid {
+            field: "config",
+            reason: e.message().to_string(),
+        },
+    })
+}
+
+/// whether the table is a valid config, otherwise the reason
+fn check(table: &Table) -> Result<(), String> {
+    Config::deserialize(Value::Table(table.clone()))
+        .map(|_| ())
+        .map_err(|e| e.message().to_string())
+}
+
+/// serde's message for a value of the wrong type, e.g. "invalid type: integer `1`, expected a string"
+fn is_type_error(message: &str) -> bool {
+    message.starts_with("invalid type")
+}
+
+/// Sets a dotted key, creating the tables on the way.
+///
+/// Numbers index into lists, e.g. `renderers.0.name`. The index right after
+/// the last entry adds a new one.
+fn set(table: &mut Table, key: &str, value: Value) -> Result<(), String> {
+    let (first, rest) = match key.split_once('.') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (key, None),
+    };
+    if first.is_empty() {
+        return Err("empty key".to_string());
+    }
+    match rest {
+        None => {
+            table.insert(first.to_string(), value);
+            Ok(())
+        }
+        Some(rest) => {
+            let child = table.entry(first).or_insert_with(|| container(rest));
+            set_in(child, first, rest, value)
+        }
+    }
+}
+
+/// set a dotted key below the table or list `name`
+fn set_in(target: &mut Value, name: &str, key: &str, value: Value) -> Result<(), String> {
+    let list = match target {
+        Value::Table(table) => return set(table, key, value),
+        Value::Array(list) => list,
+        _ => return Err(format!("`{name}` is not a table")),
+    };
+    let (index, rest) = match key.split_once('.') {
+        Some((index, rest)) => (index, Some(rest)),
+        None => (key, None),
+    };
+    let index: usize = index
+        .parse()
+        .map_err(|_| format!("`{name}` is a list, expected an index instead of `{index}`"))?;
+    if index > list.len() {
+        return Err(format!("`{name}` has {} entries, can't set entry {index}", list.len()));
+    }
+    match rest {
+        None if index == list.len() => list.push(value),
+        None => list[index] = value,
+        Some(rest) => {
+            if index == list.len() {
+                list.push(container(rest));
+            }
+            set_in(&mut list[index], &format!("{name}.{index}"), rest, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// an empty list if the key continues with an index, otherwise an empty table
+fn container(key: &str) -> Value {
+    match key.split('.').next().is_some_and(|part| part.parse::<usize>().is_ok()) {
+        true => Value::Array(Vec::new()),
+        false => Value::Table(Table::new()),
+    }
+}
+
+/// values are parsed as toml (numbers, booleans, arrays, ...),
+/// everything else is taken as a plain string. [apply] falls back to the
+/// plain string if the field doesn't accept the parsed value
+fn parse_value(value: &str) -> Value {
+    format!("value = {value}")
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renderer(name: &str) -> Value {
+        let mut table = Table::new();
+        table.insert("name".to_string(), Value::String(name.to_string()));
+        Value::Table(table)
+    }
+
+    fn names(table: &Table) -> Vec<&str> {
+        table["renderers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|renderer| renderer["name"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn set_creates_tables() {
+        let mut table = Table::new();
+        set(&mut table, "server.port", Value::Integer(5902)).unwrap();
+        assert_eq!(table["server"]["port"].as_integer(), Some(5902));
+        assert_eq!(set(&mut table, ".port", Value::Integer(1)), Err("empty key".to_string()));
+    }
+
+    #[test]
+    fn set_indexes_lists() {
+        let mut table = Table::new();
+        table.insert("renderers".to_string(), Value::Array(vec![renderer("a"), renderer("b")]));
+
+        set(&mut table, "renderers.0.name", Value::String("c".to_string())).unwrap();
+        assert_eq!(names(&table), ["c", "b"]);
+        set(&mut table, "renderers.1", renderer("d")).unwrap();
+        assert_eq!(names(&table), ["c", "d"]);
+    }
+
+    #[test]
+    fn set_appends_after_last_entry() {
+        let mut table = Table::new();
+        set(&mut table, "renderers.0.name", Value::String("a".to_string())).unwrap();
+        set(&mut table, "renderers.1.name", Value::String("b".to_string())).unwrap();
+        set(&mut table, "renderers.2", renderer("c")).unwrap();
+        assert_eq!(names(&table), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn set_rejects_invalid_index() {
+        let mut table = Table::new();
+        set(&mut table, "renderers.0.name", Value::String("a".to_string())).unwrap();
+        assert_eq!(
+            set(&mut table, "renderers.2.name", Value::String("b".to_string())),
+            Err("`renderers` has 1 entries, can't set entry 2".to_string())
+        );
+        assert_eq!(
+            set(&mut table, "renderers.name", Value::String("b".to_string())),
+            Err("`renderers` is a list, expected an index instead of `name`".to_string())
+        );
+        assert_eq!(
+            set(&mut table, "renderers.0.name.first", Value::String("b".to_string())),
+            Err("`name` is not a table".to_string())
+        );
+        assert_eq!(names(&table), ["a"]);
+    }
+
+    #[test]
+    fn env_keys() {
+        assert_eq!(env_key("server_port").as_deref(), Some("server.port"));
+        assert_eq!(env_key("app_auto_stop").as_deref(), Some("app.auto_stop"));
+        assert_eq!(env_key("renderers_0_ip_addr").as_deref(), Some("renderers.0.ip_addr"));
+        assert_eq!(env_key("profile").as_deref(), Some("profile"));
+        assert_eq!(env_key("token"), None);
+        assert_eq!(env_key("host_url"), None);
+        assert_eq!(env_key("renderers_name"), None);
+        assert_eq!(env_key("server_"), None);
+    }
+
+    #[test]
+    fn values_are_parsed_as_toml() {
+        assert_eq!(parse_value("5902"), Value::Integer(5902));
+        assert_eq!(parse_value("true"), Value::Boolean(true));
+        assert_eq!(parse_value("Built-in Audio"), Value::String("Built-in Audio".to_string()));
+    }
+
+    #[test]
+    fn apply_falls_back_to_text() {
+        let overrides = [
+            Override::new("device.name", "1", "--device"),
+            Override::new("renderers.0.name", "2", "--renderer"),
+            Override::new("renderers.0.ip_addr", "192.168.1.20", "--renderer-ip"),
+            Override::new("server.port", "5902", "--port"),
+        ];
+        let config = apply(Table::new(), &overrides).unwrap();
+        assert_eq!(config.device.unwrap().name, "1");
+        assert_eq!(config.renderers[0].name, "2");
+        assert_eq!(config.server.port, 5902);
+    }
+
+    #[test]
+    fn apply_reports_culprit() {
+        let overrides = [
+            Override::new("server.port", "high", "--port"),
+            Override::new("app.auto_stop", "300", "--set"),
+        ];
+        match apply(Table::new(), &overrides) {
+            Err(ConfigError::Override { origin, key, .. }) => {
+                assert_eq!((origin.as_str(), key.as_str()), ("--port", "server.port"))
+            }
+            other => panic!("expected an override error, got {other:?}"),
+        }
+    }
+}
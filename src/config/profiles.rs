@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use super::{Config, ConfigError, Override};
+
+/// Profile - a named set of settings, `[profiles.<name>]` in the config file
+///
+/// each section is merged into the section of the same name, so a profile
+/// only has to contain the fields it changes. a list of renderers replaces
+/// the configured renderers
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<Table>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<Table>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renderers: Option<Vec<Table>>,
+}
+
+/// the selected profile, the last `profile` override wins over the file
+pub(super) fn selected(table: &Table, overrides: &[Override]) -> Option<String> {
+    overrides
+        .iter()
+        .rev()
+        .find(|o| o.key == "profile")
+        .map(|o| o.value.clone())
+        .or_else(|| table.get("profile").and_then(Value::as_str).map(str::to_string))
+        .filter(|name| !name.is_empty())
+}
+
+/// apply - merge the selected profile into the parsed config file
+///
+/// the profile sits between the file and the overrides, so flags and
+/// environment variables still win over it
+pub(super) fn apply(table: &mut Table, overrides: &[Override]) -> Result<(), ConfigError> {
+    let Some(name) = selected(table, overrides) else {
+        return Ok(());
+    };
+    let profiles = table.get("profiles").and_then(Value::as_table);
+    let profile = match profiles.and_then(|p| p.get(&name)).and_then(Value::as_table) {
+        Some(profile) => profile.clone(),
+        None => {
+            let available: Vec<&str> = profiles.iter().flat_map(|p| p.keys()).map(String::as_str).collect();
+            let message = match available.is_empty() {
+                true => "not found, the config file has no profiles".to_string(),
+                false => format!("not found, available: {}", available.join(", ")),
+            };
+            return Err(ConfigError::Profile { name, message });
+        }
+    };
+
+    for (section, values) in profile {
+        match (table.get_mut(&section), values) {
+            (Some(Value::Table(base)), Value::Table(values)) => merge(base, values),
+            (_, values) => {
+                table.insert(section, values);
+            }
+        }
+    }
+    table.insert("profile".to_string(), Value::String(name.clone()));
+
+    // report invalid values in the profile before the overrides are applied
+    Config::deserialize(Value::Table(table.clone())).map_err(|e| ConfigError::Profile {
+        name,
+        message: e.message().to_string(),
+    })?;
+    Ok(())
+}
+
+fn merge(base: &mut Table, values: Table) {
+    for (key, value) in values {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(values)) => merge(base, values),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+profile = "office"
+
+[audio]
+bits_per_sample = 24
+gain_db = -6.0
+
+[[renderers]]
+name = "Living Room"
+ip_addr = "192.168.1.20"
+delay_ms = 40
+
+[[renderers]]
+name = "Kitchen"
+ip_addr = "192.168.1.22"
+
+[profiles.office]
+audio = { bits_per_sample = 16 }
+
+[profiles.kitchen]
+renderers = [{ name = "Kitchen", ip_addr = "192.168.1.22", gain_db = -3.0 }]
+"#;
+
+    fn load(overrides: &[Override]) -> Result<Config, ConfigError> {
+        let mut table: Table = CONFIG.parse().unwrap();
+        apply(&mut table, overrides)?;
+        Ok(Config::deserialize(Value::Table(table)).unwrap())
+    }
+
+    #[test]
+    fn profile_merges_sections() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("office"));
+        assert_eq!(config.audio.bits_per_sample, 16);
+        assert_eq!(config.audio.gain_db, -6.0);
+        // a profile without renderers keeps the configured ones
+        let names: Vec<&str> = config.renderers.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["Living Room", "Kitchen"]);
+        assert_eq!(config.renderers[0].delay_ms, 40);
+    }
+
+    #[test]
+    fn profile_replaces_renderers() {
+        let config = load(&[Override::new("profile", "kitchen", "--profile")]).unwrap();
+        assert_eq!(config.audio.bits_per_sample, 24);
+        assert_eq!(config.renderers.len(), 1);
+        assert_eq!(config.renderers[0].name, "Kitchen");
+        assert_eq!(config.renderers[0].gain_db, -3.0);
+    }
+
+    #[test]
+    fn override_selects_profile() {
+        let overrides = [
+            Override::new("profile", "kitchen", "SONAR_PROFILE"),
+            Override::new("profile", "office", "--profile"),
+        ];
+        let table: Table = CONFIG.parse().unwrap();
+        assert_eq!(selected(&table, &overrides).as_deref(), Some("office"));
+        assert_eq!(selected(&table, &[Override::new("profile", "", "--profile")]), None);
+    }
+
+    #[test]
+    fn unknown_profile() {
+        match load(&[Override::new("profile", "garden", "--profile")]) {
+            Err(ConfigError::Profile { name, message }) => {
+                assert_eq!(name, "garden");
+                assert_eq!(message, "not found, available: kitchen, office");
+            }
+            other => panic!("expected a profile error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn merge_is_deep() {
+        let mut base: Table = "[a]\nx = 1\n[a.b]\ny = 2\nz = 3\n".parse().unwrap();
+        merge(&mut base, "[a.b]\nz = 4\nlist = [1]\n".parse().unwrap());
+        assert_eq!(base["a"]["x"].as_integer(), Some(1));
+        assert_eq!(base["a"]["b"]["y"].as_integer(), Some(2));
+        assert_eq!(base["a"]["b"]["z"].as_integer(), Some(4));
+        assert_eq!(base["a"]["b"]["list"].as_array().map(Vec::len), Some(1));
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use log::{error, info};
+use parking_lot::Mutex;
+use toml::{Table, Value};
+
+use super::{Config, ConfigError, Override};
+use crate::CONFIG;
+
+/// how often the config file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// fields that are only read at startup
+const RESTART_REQUIRED: [&str; 2] = ["server.workers", "app.auto_reconnect"];
+
+/// origin of the overrides made through the api
+const API_ORIGIN: &str = "api";
+
+/// set by the SIGHUP handler
+static HANGUP: AtomicBool = AtomicBool::new(false);
+/// where the config is loaded from, shared between the watcher and the api
+static SOURCE: Mutex<Option<Source>> = Mutex::new(None);
+
+struct Source {
+    path: PathBuf,
+    /// the overrides of the environment, the command line and the api
+    overrides: Vec<Override>,
+    reloads: Sender<Reload>,
+}
+
+/// a reloaded config, already stored in `CONFIG`
+///
+/// changes that only take effect once something is restarted (the capture
+/// stream, the listener) are applied by the receiver
+#[derive(Debug)]
+pub struct Reload {
+    /// dotted paths of the fields that changed, e.g. `server.port`
+    pub changed: Vec<String>,
+}
+
+impl Reload {
+    /// whether the field or any field in the section changed
+    pub fn affects(&self, key: &str) -> bool {
+        self.changed
+            .iter()
+            .any(|c| c == key || c.strip_prefix(key).is_some_and(|rest| rest.starts_with('.')))
+    }
+
+    /// the changed fields that only take effect after a restart
+    pub fn restart_required(&self) -> Vec<&str> {
+        RESTART_REQUIRED
+            .into_iter()
+            .filter(|key| self.affects(key))
+            .collect()
+    }
+}
+
+/// start_config_watcher - reload the config when the file changes or on SIGHUP
+///
+/// the new config is loaded with the same overrides as at startup and
+/// replaces `CONFIG` if it is valid, an invalid file keeps the old config.
+/// every reload with changes is sent to the returned channel
+pub fn start_config_watcher(path: PathBuf, overrides: Vec<Override>) -> Receiver<Reload> {
+    let (s, r) = unbounded();
+    *SOURCE.lock() = Some(Source {
+        path: path.clone(),
+        overrides,
+        reloads: s,
+    });
+    handle_hangup();
+    thread::Builder::new()
+        .name("config_watcher".into())
+        .spawn(move || {
+            let mut last_modified = modified(&path);
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let hangup = HANGUP.swap(false, Ordering::Relaxed);
+                let now = modified(&path);
+                // editors briefly remove the file while saving, a missing
+                // file is no change. SIGHUP reports it as an error
+                if !hangup && (now.is_none() || now == last_modified) {
+                    continue;
+                }
+                last_modified = now;
+
+                info!("reloading config '{}'", path.display());
+                if let Some(source) = SOURCE.lock().as_ref() {
+                    if let Err(e) = reload(source) {
+                        error!("invalid config, keeping the current one: {e}");
+                    }
+                }
+            }
+        })
+        .unwrap();
+    r
+}
+
+/// select_profile - switch to another profile at runtime, an empty name disables it
+///
+/// the selection wins over the file and the command line until sonar is
+/// restarted. an unknown or invalid profile keeps the current config
+pub fn select_profile(name: &str) -> Result<(), ConfigError> {
+    let mut source = SOURCE.lock();
+    let Some(source) = source.as_mut() else {
+        return Err(ConfigError::Profile {
+            name: name.to_string(),
+            message: "the config is not loaded yet".to_string(),
+        });
+    };
+
+    let previous = source.overrides.clone();
+    source.overrides.retain(|o| o.origin != API_ORIGIN);
+    source.overrides.push(Override::new("profile", name, API_ORIGIN));
+    let result = reload(source);
+    if result.is_err() {
+        source.overrides = previous;
+    }
+    result
+}
+
+fn reload(source: &Source) -> Result<(), ConfigError> {
+    let config = load(&source.path, &source.overrides)?;
+
+    let changed = changed_fields(&CONFIG.read(), &config);
+    if changed.is_empty() {
+        info!("config unchanged");
+        return Ok(());
+    }
+    info!("config changed: {}", changed.join(", "));
+    *CONFIG.write() = config;
+    let _ = source.reloads.send(Reload { changed });
+    Ok(())
+}
+
+/// load the config for a reload
+///
+/// unlike at startup, a missing file is an error, the defaults would
+/// replace the running config
+fn load(path: &Path, overrides: &[Override]) -> Result<Config, ConfigError> {
+    if !path.exists() {
+        return Err(ConfigError::Io {
+            path: path.to_path_buf(),
+            source: io::Error::new(io::ErrorKind::NotFound, "the file does not exist"),
+        });
+    }
+    // never rewrite the file the user is editing
+    Config::load(path, true, overrides)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// the dotted paths of all fields that differ between the configs
+fn changed_fields(old: &Config, new: &Config) -> Vec<String> {
+    let mut changed = Vec::new();
+    if let (Ok(Value::Table(old)), Ok(Value::Table(new))) = (Value::try_from(old), Value::try_from(new)) {
+        diff("", &old, &new, &mut changed);
+    }
+    changed
+}
+
+fn diff(prefix: &str, old: &Table, new: &Table, changed: &mut Vec<String>) {
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for key in keys {
+        let path = match prefix {
+            "" => key.clone(),
+            prefix => format!("{prefix}.{key}"),
+        };
+        match (old.get(key), new.get(key)) {
+            (Some(Value::Table(old)), Some(Value::Table(new))) => diff(&path, old, new, changed),
+            (old, new) if old != new => changed.push(path),
+            _ => {}
+        }
+    }
+}
+
+/// reload the config on SIGHUP, the usual signal for daemons
+#[cfg(unix)]
+fn handle_hangup() {
+    extern "C" fn on_hangup(_: libc::c_int) {
+        HANGUP.store(true, Ordering::Relaxed);
+    }
+    unsafe {
+        libc::signal(libc::SIGHUP, on_hangup as *const () as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+fn handle_hangup() {}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("sonar-watch-{}-{name}.toml", std::process::id()))
+    }
+
+    #[test]
+    fn reload_of_missing_file_keeps_config() {
+        let path = temp_path("missing");
+        let _ = fs::remove_file(&path);
+        let port = CONFIG.read().server.port;
+        let (reloads, received) = unbounded();
+        let source = Source {
+            path: path.clone(),
+            overrides: vec![Override::new("server.port", "6123", "test")],
+            reloads,
+        };
+
+        assert!(matches!(load(&path, &[]), Err(ConfigError::Io { .. })));
+        assert!(reload(&source).is_err());
+        assert_eq!(CONFIG.read().server.port, port);
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn load_reads_existing_file() {
+        let path = temp_path("existing");
+        fs::write(&path, "[server]\nport = 6124\n").unwrap();
+        let config = load(&path, &[]);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(config.unwrap().server.port, 6124);
+    }
+}
@@ -0,0 +1,189 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use parking_lot::RwLock;
+
+use crate::config::Config;
+
+/// longest delay of a client
+pub const MAX_DELAY_MS: usize = 2000;
+/// fade out and in when the delay changes, the jump would click otherwise
+const FADE_MS: usize = 10;
+
+/// DelayControl - the delays set through the api
+///
+/// they replace the renderer's `delay_ms` of the config until sonar is restarted
+#[derive(Debug, Default)]
+pub struct DelayControl {
+    clients: RwLock<HashMap<IpAddr, usize>>,
+}
+
+impl DelayControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the delay of a client in milliseconds
+    pub fn delay_ms(&self, config: &Config, ip: IpAddr) -> usize {
+        if let Some(delay) = self.clients.read().get(&ip) {
+            return *delay;
+        }
+        config
+            .renderer_for(ip)
+            .map(|renderer| renderer.delay_ms)
+            .unwrap_or_default()
+    }
+
+    pub fn set(&self, ip: IpAddr, delay_ms: usize) {
+        self.clients.write().insert(ip, delay_ms);
+    }
+}
+
+/// Delay - delays interleaved samples by a number of frames
+///
+/// the buffer for the longest delay is allocated up front, so setting a delay
+/// in the streaming loop doesn't allocate. changing the delay fades out, jumps
+/// and fades in again
+pub struct Delay {
+    sample_rate: u32,
+    channels: usize,
+    /// `MAX_DELAY_MS + 1` frames
+    buffer: Vec<f32>,
+    write_pos: usize,
+    frames: usize,
+    target: usize,
+    fade: f32,
+    fade_step: f32,
+}
+
+impl Delay {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let max_frames = sample_rate as usize * MAX_DELAY_MS / 1000;
+        Self {
+            sample_rate,
+            channels,
+            buffer: vec![0.0; (max_frames + 1) * channels],
+            write_pos: 0,
+            frames: 0,
+            target: 0,
+            fade: 1.0,
+            fade_step: 1.0 / (sample_rate as usize * FADE_MS / 1000).max(1) as f32,
+        }
+    }
+
+    /// Sets the delay, it is clamped to [MAX_DELAY_MS].
+    ///
+    /// `immediate` skips the fade, for the start of a stream.
+    pub fn set_delay_ms(&mut self, delay_ms: usize, immediate: bool) {
+        let max_frames = self.sample_rate as usize * MAX_DELAY_MS / 1000;
+        let frames = (self.sample_rate as usize * delay_ms / 1000).min(max_frames);
+        self.target = frames;
+        if immediate {
+            self.frames = frames;
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let len = self.buffer.len() / self.channels;
+        for frame in samples.chunks_exact_mut(self.channels) {
+            let write = self.write_pos * self.channels;
+            self.buffer[write..write + self.channels].copy_from_slice(frame);
+            let read = (self.write_pos + len - self.frames) % len * self.channels;
+            for (sample, delayed) in frame.iter_mut().zip(&self.buffer[read..read + self.channels]) {
+                *sample = delayed * self.fade;
+            }
+            self.write_pos = (self.write_pos + 1) % len;
+
+            if self.frames != self.target {
+                self.fade -= self.fade_step;
+                if self.fade <= 0.0 {
+                    self.fade = 0.0;
+                    self.frames = self.target;
+                }
+            } else if self.fade < 1.0 {
+                self.fade = (self.fade + self.fade_step).min(1.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// runs stereo frames through the delay in chunks of 256 frames
+    fn run(delay: &mut Delay, input: &[f32]) -> Vec<f32> {
+        let mut output = input.to_vec();
+        for chunk in output.chunks_mut(256 * 2) {
+            delay.process(chunk);
+        }
+        output
+    }
+
+    fn impulse(frames: usize) -> Vec<f32> {
+        let mut input = vec![0.0; frames * 2];
+        input[0] = 1.0;
+        input[1] = -1.0;
+        input
+    }
+
+    #[test]
+    fn zero_delay_passes_samples_through() {
+        let mut delay = Delay::new(SAMPLE_RATE, 2);
+        delay.set_delay_ms(0, true);
+        let input: Vec<f32> = (0..2000).map(|i| (i as f32 * 0.37).sin()).collect();
+        assert_eq!(run(&mut delay, &input), input);
+    }
+
+    #[test]
+    fn impulse_comes_out_delay_frames_later() {
+        let mut delay = Delay::new(SAMPLE_RATE, 2);
+        delay.set_delay_ms(25, true);
+        let frames = SAMPLE_RATE as usize * 25 / 1000;
+        let output = run(&mut delay, &impulse(frames * 2));
+        for (i, &sample) in output.iter().enumerate() {
+            let expected = match i {
+                i if i == frames * 2 => 1.0,
+                i if i == frames * 2 + 1 => -1.0,
+                _ => 0.0,
+            };
+            assert_eq!(sample, expected, "sample {i}");
+        }
+    }
+
+    #[test]
+    fn delay_is_clamped_to_max() {
+        let mut delay = Delay::new(SAMPLE_RATE, 2);
+        delay.set_delay_ms(MAX_DELAY_MS * 3, true);
+        let frames = SAMPLE_RATE as usize * MAX_DELAY_MS / 1000;
+        assert_eq!(delay.frames, frames);
+        let output = run(&mut delay, &impulse(frames + 10));
+        let first = output.iter().position(|&sample| sample != 0.0);
+        assert_eq!(first, Some(frames * 2));
+    }
+
+    #[test]
+    fn changing_the_delay_fades_out_and_in() {
+        let mut delay = Delay::new(SAMPLE_RATE, 2);
+        delay.set_delay_ms(0, true);
+        let fade_frames = SAMPLE_RATE as usize * FADE_MS / 1000;
+        run(&mut delay, &vec![1.0; 1000 * 2]);
+
+        delay.set_delay_ms(20, false);
+        let output = run(&mut delay, &vec![1.0; 4 * fade_frames * 2]);
+        let left: Vec<f32> = output.iter().step_by(2).copied().collect();
+
+        // no jump in the level, it goes down to silence and back up
+        for pair in left.windows(2) {
+            assert!((pair[1] - pair[0]).abs() <= delay.fade_step + 1e-6, "{pair:?}");
+        }
+        let silent = left.iter().position(|&sample| sample == 0.0).unwrap();
+        assert!(silent.abs_diff(fade_frames) <= 1, "silent at {silent}");
+        assert!(left[..silent].windows(2).all(|pair| pair[1] < pair[0]));
+        assert!(left[silent..].windows(2).all(|pair| pair[1] >= pair[0]));
+        assert_eq!(*left.last().unwrap(), 1.0);
+        assert_eq!(delay.frames, SAMPLE_RATE as usize * 20 / 1000);
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    fmt,
+    net::IpAddr,
+};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+/// built-in presets, applied before the renderer's own bands
+pub const PRESETS: [(&str, &[Band]); 7] = [
+    ("flat", &[]),
+    ("bass-cut", &[Band::new(FilterType::LowShelf, 150.0, -6.0, 0.7)]),
+    ("bass-boost", &[Band::new(FilterType::LowShelf, 100.0, 6.0, 0.7)]),
+    ("treble-cut", &[Band::new(FilterType::HighShelf, 8000.0, -4.0, 0.7)]),
+    ("treble-boost", &[Band::new(FilterType::HighShelf, 8000.0, 4.0, 0.7)]),
+    (
+        "speech",
+        &[
+            Band::new(FilterType::HighPass, 100.0, 0.0, 0.7),
+            Band::new(FilterType::Peaking, 3000.0, 3.0, 1.0),
+        ],
+    ),
+    (
+        "loudness",
+        &[
+            Band::new(FilterType::LowShelf, 100.0, 6.0, 0.7),
+            Band::new(FilterType::HighShelf, 10000.0, 4.0, 0.7),
+        ],
+    ),
+];
+
+/// allowed frequencies of a band in Hz
+const FREQ_RANGE: std::ops::RangeInclusive<f64> = 10.0..=22000.0;
+/// allowed gain of a band in dB
+const BAND_GAIN_RANGE_DB: std::ops::RangeInclusive<f64> = -24.0..=24.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterType {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+/// a single filter of the equalizer, `[[renderer.eq]]` in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Band {
+    #[serde(rename = "type")]
+    pub kind: FilterType,
+    /// center or corner frequency in Hz
+    pub freq: f64,
+    /// ignored by the pass filters
+    #[serde(default)]
+    pub gain_db: f64,
+    #[serde(default = "default_q")]
+    pub q: f64,
+}
+
+fn default_q() -> f64 {
+    0.7
+}
+
+impl Band {
+    pub const fn new(kind: FilterType, freq: f64, gain_db: f64, q: f64) -> Self {
+        Self {
+            kind,
+            freq,
+            gain_db,
+            q,
+        }
+    }
+
+    /// check the band's values, the error describes the first invalid one
+    pub fn validate(&self) -> Result<(), String> {
+        if !FREQ_RANGE.contains(&self.freq) {
+            return Err(format!("freq must be between 10 and 22000 Hz, got {}", self.freq));
+        }
+        if !BAND_GAIN_RANGE_DB.contains(&self.gain_db) {
+            return Err(format!("gain_db must be between -24 and 24 dB, got {}", self.gain_db));
+        }
+        if !(self.q > 0.0 && self.q <= 20.0) {
+            return Err(format!("q must be between 0 and 20, got {}", self.q));
+        }
+        Ok(())
+    }
+}
+
+/// the bands of a preset
+pub fn preset(name: &str) -> Option<&'static [Band]> {
+    PRESETS.iter().find(|(n, _)| *n == name).map(|(_, bands)| *bands)
+}
+
+/// an unknown preset name
+#[derive(Debug)]
+pub struct UnknownPreset(pub String);
+
+impl fmt::Display for UnknownPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = PRESETS.iter().map(|(name, _)| *name).collect();
+        write!(f, "unknown preset '{}', available: {}", self.0, names.join(", "))
+    }
+}
+
+/// the equalizer settings of a client
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EqSettings {
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub bands: Vec<Band>,
+}
+
+impl EqSettings {
+    /// the bands of the preset followed by the client's own bands
+    pub fn filters(&self) -> Vec<Band> {
+        let preset = self.preset.as_deref().and_then(preset).unwrap_or_default();
+        preset.iter().chain(&self.bands).copied().collect()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(name) = self.preset.as_deref().filter(|name| preset(name).is_none()) {
+            return Err(UnknownPreset(name.to_string()).to_string());
+        }
+        self.bands.iter().try_for_each(Band::validate)
+    }
+}
+
+/// EqControl - the equalizer settings made through the api
+///
+/// they replace the renderer's settings of the config until sonar is restarted
+#[derive(Debug, Default)]
+pub struct EqControl {
+    clients: RwLock<HashMap<IpAddr, EqSettings>>,
+}
+
+impl EqControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn settings(&self, config: &Config, ip: IpAddr) -> EqSettings {
+        if let Some(settings) = self.clients.read().get(&ip) {
+            return settings.clone();
+        }
+        config
+            .renderer_for(ip)
+            .map(|renderer| EqSettings {
+                preset: renderer.eq_preset.clone(),
+                bands: renderer.eq.clone(),
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn set(&self, ip: IpAddr, settings: EqSettings) {
+        self.clients.write().insert(ip, settings);
+    }
+}
+
+/// biquad coefficients, normalized to a0 = 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Coefficients {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+}
+
+impl Coefficients {
+    /// the filters of the Audio EQ Cookbook (R. Bristow-Johnson)
+    fn new(band: &Band, sample_rate: u32) -> Self {
+        let nyquist = sample_rate as f64 / 2.0;
+        let freq = band.freq.min(nyquist * 0.95);
+        let w0 = 2.0 * PI * freq / sample_rate as f64;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q);
+        let a = 10f64.powf(band.gain_db / 40.0);
+
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            FilterType::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterType::LowShelf => {
+                let sq = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + sq),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - sq),
+                    (a + 1.0) + (a - 1.0) * cos + sq,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - sq,
+                )
+            }
+            FilterType::HighShelf => {
+                let sq = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + sq),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - sq),
+                    (a + 1.0) - (a - 1.0) * cos + sq,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - sq,
+                )
+            }
+            FilterType::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterType::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+/// a biquad filter in transposed direct form II, with a state per channel
+pub(super) struct Biquad {
+    coefficients: Coefficients,
+    /// `z1, z2` of every channel
+    state: Vec<[f64; 2]>,
+}
+
+impl Biquad {
+    pub(super) fn new(coefficients: Coefficients, channels: usize) -> Self {
+        Self {
+            coefficients,
+            state: vec![[0.0; 2]; channels],
+        }
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.state.fill([0.0; 2]);
+    }
+
+    #[inline]
+    pub(super) fn process(&mut self, sample: f32, channel: usize) -> f32 {
+        let c = &self.coefficients;
+        let z = &mut self.state[channel];
+        let x = sample as f64;
+        let y = c.b0 * x + z[0];
+        z[0] = c.b1 * x - c.a1 * y + z[1];
+        z[1] = c.b2 * x - c.a2 * y;
+        y as f32
+    }
+}
+
+/// Equalizer - a chain of biquad filters applied to interleaved samples
+pub struct Equalizer {
+    sample_rate: u32,
+    channels: usize,
+    filters: Vec<Biquad>,
+}
+
+impl Equalizer {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            sample_rate,
+            channels: channels.max(1),
+            filters: Vec::new(),
+        }
+    }
+
+    /// Replaces the filters.
+    ///
+    /// If the number of bands stays the same, the filter state is kept,
+    /// so changing a band while playing does not click.
+    pub fn set_bands(&mut self, bands: &[Band]) {
+        if bands.len() == self.filters.len() {
+            for (filter, band) in self.filters.iter_mut().zip(bands) {
+                filter.coefficients = Coefficients::new(band, self.sample_rate);
+            }
+            return;
+        }
+        self.filters = bands
+            .iter()
+            .map(|band| Biquad::new(Coefficients::new(band, self.sample_rate), self.channels))
+            .collect();
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if self.filters.is_empty() {
+            return;
+        }
+        for frame in samples.chunks_exact_mut(self.channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = self
+                    .filters
+                    .iter_mut()
+                    .fold(*sample, |sample, filter| filter.process(sample, channel));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// magnitude response of the filter at `freq` in dB
+    fn response_db(c: &Coefficients, freq: f64) -> f64 {
+        let w = 2.0 * PI * freq / SAMPLE_RATE as f64;
+        // evaluate b(z) and a(z) at z = e^jw as (re, im)
+        let eval = |k0: f64, k1: f64, k2: f64| {
+            (
+                k0 + k1 * w.cos() + k2 * (2.0 * w).cos(),
+                -k1 * w.sin() - k2 * (2.0 * w).sin(),
+            )
+        };
+        let (b_re, b_im) = eval(c.b0, c.b1, c.b2);
+        let (a_re, a_im) = eval(1.0, c.a1, c.a2);
+        10.0 * ((b_re * b_re + b_im * b_im) / (a_re * a_re + a_im * a_im)).log10()
+    }
+
+    fn response_of(kind: FilterType, freq: f64, gain_db: f64, q: f64) -> impl Fn(f64) -> f64 {
+        let coefficients = Coefficients::new(&Band::new(kind, freq, gain_db, q), SAMPLE_RATE);
+        move |f| response_db(&coefficients, f)
+    }
+
+    fn assert_db(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.01, "{actual} dB, expected {expected} dB");
+    }
+
+    #[test]
+    fn peaking_has_its_gain_at_the_center() {
+        for gain_db in [-12.0, 6.0] {
+            let response = response_of(FilterType::Peaking, 1000.0, gain_db, 1.0);
+            assert_db(response(1000.0), gain_db);
+            assert_db(response(10.0), 0.0);
+            assert_db(response(23000.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn shelves_have_half_their_gain_at_the_corner() {
+        let low = response_of(FilterType::LowShelf, 200.0, 6.0, FRAC_1_SQRT_2);
+        assert_db(low(200.0), 3.0);
+        assert_db(low(1.0), 6.0);
+        assert_db(low(23000.0), 0.0);
+
+        let high = response_of(FilterType::HighShelf, 5000.0, -8.0, FRAC_1_SQRT_2);
+        assert_db(high(5000.0), -4.0);
+        assert_db(high(1.0), 0.0);
+        assert_db(high(24000.0), -8.0);
+    }
+
+    #[test]
+    fn pass_filters_are_3_db_down_at_the_corner() {
+        let low = response_of(FilterType::LowPass, 2000.0, 0.0, FRAC_1_SQRT_2);
+        assert_db(low(2000.0), -3.01);
+        assert_db(low(1.0), 0.0);
+        assert!(low(20000.0) < -30.0);
+
+        let high = response_of(FilterType::HighPass, 80.0, 0.0, FRAC_1_SQRT_2);
+        assert_db(high(80.0), -3.01);
+        assert_db(high(24000.0), 0.0);
+        assert!(high(10.0) < -30.0);
+    }
+
+    #[test]
+    fn presets_are_valid() {
+        for (name, bands) in PRESETS {
+            assert_eq!(preset(name), Some(bands));
+            for band in bands {
+                assert_eq!(band.validate(), Ok(()), "preset {name}");
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_bands_are_rejected() {
+        let settings = |band: Band| EqSettings {
+            preset: None,
+            bands: vec![Band::new(FilterType::Peaking, 1000.0, 3.0, 1.0), band],
+        };
+        let valid = Band::new(FilterType::Peaking, 1000.0, 3.0, 1.0);
+        assert_eq!(settings(valid).validate(), Ok(()));
+
+        for (band, field) in [
+            (Band { freq: 5.0, ..valid }, "freq"),
+            (Band { freq: 30000.0, ..valid }, "freq"),
+            (Band { q: 0.0, ..valid }, "q"),
+            (Band { q: 25.0, ..valid }, "q"),
+            (Band { q: f64::NAN, ..valid }, "q"),
+            (Band { gain_db: -30.0, ..valid }, "gain_db"),
+            (Band { gain_db: 25.0, ..valid }, "gain_db"),
+        ] {
+            let err = settings(band).validate().unwrap_err();
+            assert!(err.starts_with(field), "{band:?}: {err}");
+        }
+
+        let unknown = EqSettings {
+            preset: Some("disco".to_string()),
+            bands: Vec::new(),
+        };
+        assert!(unknown.validate().unwrap_err().contains("disco"));
+    }
+
+    #[test]
+    fn changing_a_band_keeps_the_filter_state() {
+        let mut equalizer = Equalizer::new(SAMPLE_RATE, 2);
+        equalizer.set_bands(&[Band::new(FilterType::Peaking, 1000.0, 6.0, 1.0)]);
+        let mut samples: Vec<f32> = (0..960).map(|i| (i as f32 * 0.1).sin()).collect();
+        equalizer.process(&mut samples);
+        let state = equalizer.filters[0].state.clone();
+        assert_ne!(state, vec![[0.0; 2]; 2]);
+
+        let band = Band::new(FilterType::Peaking, 1000.0, -6.0, 1.0);
+        equalizer.set_bands(&[band]);
+        assert_eq!(equalizer.filters[0].state, state);
+        assert_eq!(equalizer.filters[0].coefficients, Coefficients::new(&band, SAMPLE_RATE));
+
+        // another number of bands starts over
+        equalizer.set_bands(&[band, band]);
+        assert!(equalizer.filters.iter().all(|filter| filter.state == vec![[0.0; 2]; 2]));
+    }
+}
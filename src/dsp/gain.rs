@@ -0,0 +1,152 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+/// length of a volume change, long enough to avoid clicks
+const RAMP_MS: usize = 20;
+
+/// gain and mute of a client or of all clients
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Volume {
+    pub gain_db: f32,
+    pub mute: bool,
+}
+
+/// a change of a [Volume] through the api, missing fields are kept
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct VolumeUpdate {
+    pub gain_db: Option<f32>,
+    pub mute: Option<bool>,
+}
+
+impl Volume {
+    pub fn update(self, update: VolumeUpdate) -> Self {
+        Self {
+            gain_db: update.gain_db.unwrap_or(self.gain_db),
+            mute: update.mute.unwrap_or(self.mute),
+        }
+    }
+}
+
+/// VolumeControl - the volume settings made through the api
+///
+/// they take precedence over the config until sonar is restarted.
+/// the global volume applies to all clients on top of their own
+#[derive(Debug, Default)]
+pub struct VolumeControl {
+    global: RwLock<Option<Volume>>,
+    clients: RwLock<HashMap<IpAddr, Volume>>,
+}
+
+impl VolumeControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the volume of all clients
+    pub fn global(&self, config: &Config) -> Volume {
+        self.global.read().unwrap_or(Volume {
+            gain_db: config.audio.gain_db,
+            mute: config.audio.mute,
+        })
+    }
+
+    /// the volume of a single client, without the global volume
+    pub fn client(&self, config: &Config, ip: IpAddr) -> Volume {
+        if let Some(volume) = self.clients.read().get(&ip) {
+            return *volume;
+        }
+        config
+            .renderer_for(ip)
+            .map(|renderer| Volume {
+                gain_db: renderer.gain_db,
+                mute: renderer.mute,
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn set_global(&self, volume: Volume) {
+        *self.global.write() = Some(volume);
+    }
+
+    pub fn set_client(&self, ip: IpAddr, volume: Volume) {
+        self.clients.write().insert(ip, volume);
+    }
+
+    /// Linear gain for the samples sent to a client.
+    ///
+    /// The volume of a renderer with `volume_sync` is set on the renderer
+    /// instead, only the global volume is applied.
+    pub fn gain(&self, config: &Config, ip: IpAddr) -> f32 {
+        let global = self.global(config);
+        let client = match config.renderer_for(ip) {
+            Some(renderer) if renderer.volume_sync => Volume::default(),
+            _ => self.client(config, ip),
+        };
+        if global.mute || client.mute {
+            0.0
+        } else {
+            db_to_gain(global.gain_db + client.gain_db)
+        }
+    }
+}
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Gain - applies a linear gain, changes are ramped over [RAMP_MS]
+pub struct Gain {
+    channels: usize,
+    ramp_frames: usize,
+    current: f32,
+    target: f32,
+    step: f32,
+}
+
+impl Gain {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            channels: channels.max(1),
+            ramp_frames: (sample_rate as usize * RAMP_MS / 1000).max(1),
+            current: 1.0,
+            target: 1.0,
+            step: 0.0,
+        }
+    }
+
+    /// set the gain without a ramp
+    pub fn reset(&mut self, gain: f32) {
+        self.current = gain;
+        self.target = gain;
+        self.step = 0.0;
+    }
+
+    /// ramp to a new gain
+    pub fn set_target(&mut self, gain: f32) {
+        self.target = gain;
+        self.step = (gain - self.current) / self.ramp_frames as f32;
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if self.current == self.target && self.current == 1.0 {
+            return;
+        }
+        for frame in samples.chunks_exact_mut(self.channels) {
+            if self.current != self.target {
+                self.current += self.step;
+                if (self.step > 0.0 && self.current >= self.target)
+                    || (self.step < 0.0 && self.current <= self.target)
+                {
+                    self.current = self.target;
+                }
+            }
+            for sample in frame {
+                *sample *= self.current;
+            }
+        }
+    }
+}
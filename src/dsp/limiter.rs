@@ -0,0 +1,275 @@
+use std::{collections::VecDeque, f32::consts::PI, time::Duration};
+
+/// how far the limiter looks ahead, this delays the stream by the same amount
+const LOOKAHEAD_MS: usize = 5;
+/// the delay the limiter adds to the stream, `audio.limiter` only
+pub const LOOKAHEAD: Duration = Duration::from_millis(LOOKAHEAD_MS as u64);
+/// taps of the interpolation filter per oversampled phase
+const TAPS: usize = 8;
+/// oversampling factor of the true-peak detector
+const OVERSAMPLING: usize = 4;
+/// gain below which the limiter counts as reducing
+const REDUCING: f32 = 0.999;
+
+/// Limiter - a true-peak look-ahead limiter
+///
+/// peaks between samples are estimated by 4x oversampling (as in ITU-R
+/// BS.1770), so the signal stays below the ceiling after the renderer's
+/// reconstruction filter too. the gain is lowered smoothly during the look-ahead
+/// before a peak and released exponentially afterwards
+pub struct Limiter {
+    channels: usize,
+    /// linear ceiling
+    ceiling: f32,
+    /// release coefficient per frame
+    release: f32,
+    lookahead: usize,
+    /// interpolation filter for the phases between two samples
+    phases: [[f32; TAPS]; OVERSAMPLING - 1],
+    /// the last samples of every channel, oldest first
+    history: Vec<[f32; TAPS]>,
+    /// frame index and required gain of the look-ahead window, increasing gains
+    hold: VecDeque<(u64, f32)>,
+    frame: u64,
+    envelope: f32,
+    /// moving average of the envelope over the look-ahead
+    smoothing: Vec<f32>,
+    smoothing_pos: usize,
+    smoothing_sum: f64,
+    /// the delayed samples
+    delay: Vec<f32>,
+    delay_pos: usize,
+    reducing: bool,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: u32, channels: usize, ceiling_db: f32, release_ms: usize) -> Self {
+        let channels = channels.max(1);
+        let lookahead = (sample_rate as usize * LOOKAHEAD_MS / 1000).max(1);
+        // the detector lags half of the interpolation filter behind
+        let delay_frames = lookahead - 1 + TAPS / 2;
+
+        let mut phases = [[0f32; TAPS]; OVERSAMPLING - 1];
+        for (k, phase) in phases.iter_mut().enumerate() {
+            for (t, tap) in phase.iter_mut().enumerate() {
+                let x = t as f32 - (TAPS / 2 - 1) as f32 - (k + 1) as f32 / OVERSAMPLING as f32;
+                *tap = sinc(x) * hann(x, TAPS as f32 / 2.0);
+            }
+        }
+
+        let mut limiter = Self {
+            channels,
+            ceiling: 1.0,
+            release: 0.0,
+            lookahead,
+            phases,
+            history: vec![[0.0; TAPS]; channels],
+            hold: VecDeque::with_capacity(lookahead + 1),
+            frame: 0,
+            envelope: 1.0,
+            smoothing: vec![1.0; lookahead],
+            smoothing_pos: 0,
+            smoothing_sum: lookahead as f64,
+            delay: vec![0.0; delay_frames * channels],
+            delay_pos: 0,
+            reducing: false,
+        };
+        limiter.set_params(sample_rate, ceiling_db, release_ms);
+        limiter
+    }
+
+    /// change the ceiling and release, keeps the state
+    pub fn set_params(&mut self, sample_rate: u32, ceiling_db: f32, release_ms: usize) {
+        self.ceiling = 10f32.powf(ceiling_db / 20.0);
+        let release_frames = (sample_rate as usize * release_ms / 1000).max(1) as f32;
+        self.release = (-1.0 / release_frames).exp();
+    }
+
+    /// forget the audio seen so far, used when the limiter is turned on again
+    pub fn reset(&mut self) {
+        self.history.fill([0.0; TAPS]);
+        self.hold.clear();
+        self.envelope = 1.0;
+        self.smoothing.fill(1.0);
+        self.smoothing_pos = 0;
+        self.smoothing_sum = self.lookahead as f64;
+        self.delay.fill(0.0);
+        self.delay_pos = 0;
+        self.reducing = false;
+    }
+
+    /// Limits interleaved samples in place.
+    ///
+    /// Returns the number of times the limiter started reducing the gain.
+    pub fn process(&mut self, samples: &mut [f32]) -> u64 {
+        let mut events = 0;
+        for frame in samples.chunks_exact_mut(self.channels) {
+            let peak = self.true_peak(frame);
+            let gain = self.gain(peak);
+            if gain < REDUCING && !self.reducing {
+                events += 1;
+            }
+            self.reducing = gain < REDUCING;
+
+            // swap the frame with the delayed one and apply the gain
+            let start = self.delay_pos * self.channels;
+            for (sample, delayed) in frame.iter_mut().zip(&mut self.delay[start..start + self.channels]) {
+                let input = *sample;
+                *sample = *delayed * gain;
+                *delayed = input;
+            }
+            self.delay_pos = (self.delay_pos + 1) % (self.delay.len() / self.channels);
+        }
+        events
+    }
+
+    /// the highest (interpolated) peak around the frame [TAPS] / 2 frames ago
+    fn true_peak(&mut self, frame: &[f32]) -> f32 {
+        let mut peak = 0f32;
+        for (history, &sample) in self.history.iter_mut().zip(frame) {
+            history.rotate_left(1);
+            history[TAPS - 1] = sample;
+
+            peak = peak.max(history[TAPS / 2 - 1].abs());
+            for phase in &self.phases {
+                let interpolated: f32 = history.iter().zip(phase).map(|(x, h)| x * h).sum();
+                peak = peak.max(interpolated.abs());
+            }
+        }
+        peak
+    }
+
+    /// the gain for the frame leaving the delay line
+    fn gain(&mut self, peak: f32) -> f32 {
+        let required = if peak > self.ceiling { self.ceiling / peak } else { 1.0 };
+
+        // minimum of the required gains over the look-ahead
+        while self.hold.back().is_some_and(|&(_, gain)| gain >= required) {
+            self.hold.pop_back();
+        }
+        self.hold.push_back((self.frame, required));
+        while self
+            .hold
+            .front()
+            .is_some_and(|&(frame, _)| frame + self.lookahead as u64 <= self.frame)
+        {
+            self.hold.pop_front();
+        }
+        self.frame += 1;
+        let held = self.hold.front().map_or(1.0, |&(_, gain)| gain);
+
+        // instant attack (smoothed below), exponential release
+        self.envelope = if held < self.envelope {
+            held
+        } else {
+            held + (self.envelope - held) * self.release
+        };
+
+        // the moving average ramps the gain down over the look-ahead,
+        // reaching the required gain when the peak leaves the delay line
+        self.smoothing_sum += (self.envelope - self.smoothing[self.smoothing_pos]) as f64;
+        self.smoothing[self.smoothing_pos] = self.envelope;
+        self.smoothing_pos = (self.smoothing_pos + 1) % self.lookahead;
+        (self.smoothing_sum / self.lookahead as f64).min(1.0) as f32
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// hann window of the given half width
+fn hann(x: f32, half_width: f32) -> f32 {
+    if x.abs() >= half_width {
+        0.0
+    } else {
+        0.5 * (1.0 + (PI * x / half_width).cos())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn db(gain: f32) -> f32 {
+        20.0 * gain.log10()
+    }
+
+    /// the peak of the band limited signal, by 16x windowed sinc interpolation
+    fn true_peak(samples: &[f32]) -> f32 {
+        const HALF_WIDTH: usize = 32;
+        let mut peak = 0f32;
+        for i in HALF_WIDTH..samples.len() - HALF_WIDTH {
+            for k in 0..16 {
+                let t = i as f32 + k as f32 / 16.0;
+                let value: f32 = (i - HALF_WIDTH..i + HALF_WIDTH)
+                    .map(|j| {
+                        let x = t - j as f32;
+                        samples[j] * sinc(x) * hann(x, HALF_WIDTH as f32)
+                    })
+                    .sum();
+                peak = peak.max(value.abs());
+            }
+        }
+        peak
+    }
+
+    #[test]
+    fn inter_sample_peaks_stay_below_ceiling() {
+        let ceiling_db = -1.0;
+        let mut limiter = Limiter::new(SAMPLE_RATE, 2, ceiling_db, 100);
+        // a quarter of the sample rate, shifted by 45°: every sample is at
+        // 0.707 of the amplitude, the peaks are between the samples
+        let mut samples: Vec<f32> = (0..SAMPLE_RATE as usize / 10)
+            .flat_map(|n| {
+                let x = (PI / 2.0 * n as f32 + PI / 4.0).sin();
+                [x, -x]
+            })
+            .collect();
+        let sample_peak = samples.iter().fold(0f32, |peak, x| peak.max(x.abs()));
+        assert!(db(sample_peak) < ceiling_db);
+
+        limiter.process(&mut samples);
+        let settled: Vec<f32> = samples.iter().step_by(2).skip(SAMPLE_RATE as usize / 50).copied().collect();
+        let peak = true_peak(&settled);
+        assert!(db(peak) <= ceiling_db + 0.1, "true peak {} dB", db(peak));
+        assert!(db(peak) > ceiling_db - 0.5, "true peak {} dB", db(peak));
+    }
+
+    #[test]
+    fn lookahead_matches_gain_envelope() {
+        let ceiling = 10f32.powf(-6.0 / 20.0);
+        let mut limiter = Limiter::new(SAMPLE_RATE, 1, -6.0, 100);
+        let lookahead = limiter.lookahead;
+        let delay = limiter.delay.len();
+        let spike = 2000;
+        let mut samples = vec![0.25f32; 4000];
+        samples[spike] = 1.0;
+        let input = samples.clone();
+        limiter.process(&mut samples);
+
+        // the output is the input, delayed by the look-ahead and the detector
+        assert_eq!(delay, lookahead - 1 + TAPS / 2);
+        assert!(samples[..delay].iter().all(|&x| x == 0.0));
+        let gains: Vec<f32> = samples[delay..].iter().zip(&input).map(|(out, x)| out / x).collect();
+
+        // the gain starts to go down one look-ahead before the peak leaves the delay line
+        let start = gains.iter().position(|&gain| gain < 1.0).unwrap();
+        assert_eq!(spike - start, lookahead);
+        assert!(gains[..start].iter().all(|&gain| gain == 1.0));
+        assert!(gains[start..=spike].windows(2).all(|pair| pair[1] <= pair[0]));
+        // and reaches the required gain with the peak
+        let lowest = gains.iter().copied().fold(1f32, f32::min);
+        assert_eq!(gains[spike], lowest);
+        assert!(samples[delay + spike] <= ceiling * 1.0001);
+        assert!(gains[spike] >= ceiling * 0.999);
+
+        assert_eq!(LOOKAHEAD.as_secs_f64(), lookahead as f64 / SAMPLE_RATE as f64);
+    }
+}
@@ -0,0 +1,284 @@
+use std::f64::consts::PI;
+
+use super::eq::{Biquad, Coefficients};
+
+/// step between two measuring blocks
+const STEP_MS: usize = 100;
+/// a measuring block is 400ms long, overlapping by 75%
+const STEPS_PER_BLOCK: usize = 4;
+/// blocks the loudness is measured over, the normalizer follows changes this slowly
+const WINDOW_BLOCKS: usize = 100;
+/// blocks below this loudness are ignored (absolute gate)
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// blocks this far below the ungated loudness are ignored (relative gate)
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// how fast the gain may fall when the audio gets louder
+const ATTACK_DB_PER_S: f64 = 6.0;
+/// how fast the gain may rise when the audio gets quieter
+const RELEASE_DB_PER_S: f64 = 1.0;
+/// most the normalizer lowers the volume
+const MAX_CUT_DB: f64 = 30.0;
+
+/// LoudnessNormalizer - slowly moves the loudness of the audio towards a target
+///
+/// the loudness is measured as in ITU-R BS.1770 / EBU R128: K-weighted mean
+/// square of 400ms blocks, gated absolutely at -70 LUFS and relatively at
+/// -10 LU, over the last 10 seconds. the gain follows the measurement at a few
+/// dB per second and is held during silence, so quiet passages are not pumped up
+pub struct LoudnessNormalizer {
+    channels: usize,
+    target_lufs: f64,
+    max_gain_db: f64,
+    /// the K-weighting filters, high shelf and high pass
+    shelf: Biquad,
+    high_pass: Biquad,
+    /// frames per measuring step
+    step_frames: usize,
+    step_pos: usize,
+    /// sum of the K-weighted squares of the current step
+    step_sum: f64,
+    /// mean squares of the last steps
+    steps: [f64; STEPS_PER_BLOCK],
+    step_count: usize,
+    /// mean squares of the last blocks
+    blocks: Vec<f64>,
+    block_pos: usize,
+    gain_db: f64,
+    /// `gain_db` as a factor
+    gain: f32,
+    target_gain_db: f64,
+    attack: f64,
+    release: f64,
+}
+
+impl LoudnessNormalizer {
+    pub fn new(sample_rate: u32, channels: usize, target_lufs: f32, max_gain_db: f32) -> Self {
+        let channels = channels.max(1);
+        let (shelf, high_pass) = k_weighting(sample_rate as f64);
+        Self {
+            channels,
+            target_lufs: target_lufs as f64,
+            max_gain_db: max_gain_db as f64,
+            shelf: Biquad::new(shelf, channels),
+            high_pass: Biquad::new(high_pass, channels),
+            step_frames: (sample_rate as usize * STEP_MS / 1000).max(1),
+            step_pos: 0,
+            step_sum: 0.0,
+            steps: [0.0; STEPS_PER_BLOCK],
+            step_count: 0,
+            blocks: Vec::with_capacity(WINDOW_BLOCKS),
+            block_pos: 0,
+            gain_db: 0.0,
+            gain: 1.0,
+            target_gain_db: 0.0,
+            attack: ATTACK_DB_PER_S / sample_rate as f64,
+            release: RELEASE_DB_PER_S / sample_rate as f64,
+        }
+    }
+
+    /// change the target, keeps the measurement
+    pub fn set_params(&mut self, target_lufs: f32, max_gain_db: f32) {
+        self.target_lufs = target_lufs as f64;
+        self.max_gain_db = max_gain_db as f64;
+        self.update_target();
+    }
+
+    /// forget the measurement and the gain, used when the normalizer is turned on again
+    pub fn reset(&mut self) {
+        self.shelf.reset();
+        self.high_pass.reset();
+        self.step_pos = 0;
+        self.step_sum = 0.0;
+        self.steps = [0.0; STEPS_PER_BLOCK];
+        self.step_count = 0;
+        self.blocks.clear();
+        self.block_pos = 0;
+        self.gain_db = 0.0;
+        self.gain = 1.0;
+        self.target_gain_db = 0.0;
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let weighted = self.high_pass.process(self.shelf.process(sample, channel), channel);
+                self.step_sum += (weighted as f64) * (weighted as f64);
+            }
+            self.step_pos += 1;
+            if self.step_pos == self.step_frames {
+                self.end_step();
+            }
+
+            if self.gain_db != self.target_gain_db {
+                let delta = self.target_gain_db - self.gain_db;
+                self.gain_db += delta.clamp(-self.attack, self.release);
+                self.gain = 10f64.powf(self.gain_db / 20.0) as f32;
+            }
+            for sample in frame {
+                *sample *= self.gain;
+            }
+        }
+    }
+
+    fn end_step(&mut self) {
+        // channels are summed, the mean is taken over the frames
+        self.steps[self.step_count % STEPS_PER_BLOCK] = self.step_sum / self.step_frames as f64;
+        self.step_count += 1;
+        self.step_sum = 0.0;
+        self.step_pos = 0;
+        if self.step_count < STEPS_PER_BLOCK {
+            return;
+        }
+
+        let block = self.steps.iter().sum::<f64>() / STEPS_PER_BLOCK as f64;
+        if self.blocks.len() < WINDOW_BLOCKS {
+            self.blocks.push(block);
+        } else {
+            self.blocks[self.block_pos] = block;
+        }
+        self.block_pos = (self.block_pos + 1) % WINDOW_BLOCKS;
+        self.update_target();
+    }
+
+    /// the gated loudness of the window in LUFS, `None` if everything is gated
+    fn loudness(&self) -> Option<f64> {
+        let gated_mean = |threshold: f64| {
+            let (sum, n) = self
+                .blocks
+                .iter()
+                .filter(|&&block| lufs(block) > threshold)
+                .fold((0.0, 0usize), |(sum, n), block| (sum + block, n + 1));
+            (n > 0).then(|| sum / n as f64)
+        };
+        let ungated = gated_mean(ABSOLUTE_GATE_LUFS)?;
+        let relative = (lufs(ungated) + RELATIVE_GATE_LU).max(ABSOLUTE_GATE_LUFS);
+        gated_mean(relative).map(lufs)
+    }
+
+    fn update_target(&mut self) {
+        // hold the gain while there is nothing to measure
+        if let Some(loudness) = self.loudness() {
+            self.target_gain_db = (self.target_lufs - loudness).clamp(-MAX_CUT_DB, self.max_gain_db);
+        }
+    }
+}
+
+fn lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.max(1e-20).log10()
+}
+
+/// the two K-weighting filters of ITU-R BS.1770 for any sample rate
+fn k_weighting(sample_rate: f64) -> (Coefficients, Coefficients) {
+    // stage 1: high shelf modelling the acoustic effect of the head
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Coefficients {
+        b0: (vh + vb * k / q + k * k) / a0,
+        b1: 2.0 * (k * k - vh) / a0,
+        b2: (vh - vb * k / q + k * k) / a0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+    };
+
+    // stage 2: revised low frequency B curve, a high pass
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Coefficients {
+        b0: 1.0,
+        b1: -2.0,
+        b2: 1.0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+    };
+
+    (shelf, high_pass)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// feeds `seconds` of a stereo 1 kHz sine with the given peak level,
+    /// in 10ms chunks, calling `chunk` after each one
+    fn feed(
+        normalizer: &mut LoudnessNormalizer,
+        frame: &mut usize,
+        level_dbfs: f64,
+        seconds: f64,
+        mut chunk: impl FnMut(&LoudnessNormalizer),
+    ) {
+        let amplitude = 10f64.powf(level_dbfs / 20.0);
+        let chunk_frames = SAMPLE_RATE as usize / 100;
+        let mut samples = vec![0f32; chunk_frames * 2];
+        for _ in 0..(seconds * 100.0) as usize {
+            for pair in samples.chunks_exact_mut(2) {
+                let t = *frame as f64 / SAMPLE_RATE as f64;
+                let sample = (amplitude * (2.0 * PI * 1000.0 * t).sin()) as f32;
+                pair.fill(sample);
+                *frame += 1;
+            }
+            normalizer.process(&mut samples);
+            chunk(normalizer);
+        }
+    }
+
+    #[test]
+    fn sine_at_minus_20_dbfs_measures_minus_20_lufs() {
+        let mut normalizer = LoudnessNormalizer::new(SAMPLE_RATE, 2, -20.0, 10.0);
+        feed(&mut normalizer, &mut 0, -20.0, 5.0, |_| ());
+        let loudness = normalizer.loudness().unwrap();
+        assert!((loudness + 20.0).abs() < 0.1, "{loudness} LUFS");
+    }
+
+    #[test]
+    fn silence_is_gated_and_holds_the_gain() {
+        let mut normalizer = LoudnessNormalizer::new(SAMPLE_RATE, 2, -20.0, 10.0);
+        feed(&mut normalizer, &mut 0, -100.0, 5.0, |_| ());
+        assert_eq!(normalizer.loudness(), None);
+        assert_eq!(normalizer.gain_db, 0.0);
+
+        // 10 dB too loud, the gain settles at -10 dB and stays there
+        let mut normalizer = LoudnessNormalizer::new(SAMPLE_RATE, 2, -20.0, 10.0);
+        let mut frame = 0;
+        feed(&mut normalizer, &mut frame, -10.0, 5.0, |_| ());
+        assert!((normalizer.gain_db + 10.0).abs() < 0.1, "{} dB", normalizer.gain_db);
+        // the blocks overlapping the end of the tone still count
+        feed(&mut normalizer, &mut frame, -100.0, 1.0, |_| ());
+        let gain_db = normalizer.gain_db;
+        assert!((gain_db + 10.0).abs() < 0.25, "{gain_db} dB");
+        // as long as the tone is in the window
+        feed(&mut normalizer, &mut frame, -100.0, 3.5, |normalizer| {
+            assert_eq!(normalizer.gain_db, gain_db);
+        });
+    }
+
+    #[test]
+    fn gain_moves_at_most_at_attack_and_release_rate() {
+        let mut normalizer = LoudnessNormalizer::new(SAMPLE_RATE, 2, -20.0, 20.0);
+        let mut frame = 0;
+        let chunk_seconds = 0.01;
+        let mut last = normalizer.gain_db;
+        let mut check = |normalizer: &LoudnessNormalizer| {
+            let delta = normalizer.gain_db - last;
+            assert!(delta <= RELEASE_DB_PER_S * chunk_seconds + 1e-9, "rise {delta} dB");
+            assert!(-delta <= ATTACK_DB_PER_S * chunk_seconds + 1e-9, "fall {delta} dB");
+            last = normalizer.gain_db;
+        };
+
+        // too quiet, the gain rises
+        feed(&mut normalizer, &mut frame, -40.0, 5.0, &mut check);
+        let raised = normalizer.gain_db;
+        assert!(raised > RELEASE_DB_PER_S * 4.0, "{raised} dB");
+
+        // too loud, the gain falls
+        feed(&mut normalizer, &mut frame, -5.0, 2.0, &mut check);
+        let fallen = raised - normalizer.gain_db;
+        assert!(fallen > ATTACK_DB_PER_S, "{fallen} dB");
+    }
+}
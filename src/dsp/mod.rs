@@ -0,0 +1,129 @@
+pub mod delay;
+pub mod eq;
+pub mod gain;
+pub mod limiter;
+pub mod loudness;
+
+use std::{
+    net::IpAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{CONFIG, DELAYS, EQUALIZERS, VOLUMES};
+
+use self::{delay::Delay, eq::Equalizer, gain::Gain, limiter::Limiter, loudness::LoudnessNormalizer};
+
+/// bumped whenever a setting of the pipelines changes
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// tell all pipelines to pick up changed settings (config reload, api)
+pub fn settings_changed() {
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Pipeline - the processing of a client's samples between the ring and the encoder
+///
+/// the settings are read again only after [settings_changed] was called,
+/// processing itself does not lock or allocate
+pub struct Pipeline {
+    ip: IpAddr,
+    /// the generation of the settings in use, `None` before the first chunk
+    generation: Option<u64>,
+    sample_rate: u32,
+    /// the first stage, bypassed while `audio.loudness_normalize` is off
+    normalizer: LoudnessNormalizer,
+    normalize: bool,
+    equalizer: Equalizer,
+    gain: Gain,
+    /// the final stage, bypassed while `audio.limiter` is off
+    limiter: Limiter,
+    limit: bool,
+    /// aligns the client with other rooms or a video, after the processing
+    delay: Delay,
+}
+
+impl Pipeline {
+    /// builds every stage, also the disabled ones, so that turning one on
+    /// later doesn't allocate on the streaming thread
+    pub fn new(ip: IpAddr, sample_rate: u32, channels: usize) -> Self {
+        let config = CONFIG.read();
+        let audio = &config.audio;
+        Self {
+            ip,
+            generation: None,
+            sample_rate,
+            normalizer: LoudnessNormalizer::new(
+                sample_rate,
+                channels,
+                audio.loudness_target_lufs,
+                audio.loudness_max_gain_db,
+            ),
+            normalize: false,
+            equalizer: Equalizer::new(sample_rate, channels),
+            gain: Gain::new(sample_rate, channels),
+            limiter: Limiter::new(
+                sample_rate,
+                channels,
+                audio.limiter_ceiling_db,
+                audio.limiter_release_ms,
+            ),
+            limit: false,
+            delay: Delay::new(sample_rate, channels),
+        }
+    }
+
+    /// Processes interleaved samples in place.
+    ///
+    /// Returns the number of times the limiter started reducing the gain.
+    pub fn process(&mut self, samples: &mut [f32]) -> u64 {
+        let generation = GENERATION.load(Ordering::Relaxed);
+        if self.generation != Some(generation) {
+            self.configure(generation);
+        }
+        if self.normalize {
+            self.normalizer.process(samples);
+        }
+        self.equalizer.process(samples);
+        self.gain.process(samples);
+        let limited = match self.limit {
+            true => self.limiter.process(samples),
+            false => 0,
+        };
+        self.delay.process(samples);
+        limited
+    }
+
+    fn configure(&mut self, generation: u64) {
+        let config = CONFIG.read();
+        self.equalizer
+            .set_bands(&EQUALIZERS.settings(&config, self.ip).filters());
+        let audio = &config.audio;
+        // a stage that is turned on again starts without the old state
+        if audio.loudness_normalize && !self.normalize {
+            self.normalizer.reset();
+        }
+        self.normalize = audio.loudness_normalize;
+        self.normalizer
+            .set_params(audio.loudness_target_lufs, audio.loudness_max_gain_db);
+        if audio.limiter && !self.limit {
+            self.limiter.reset();
+        }
+        self.limit = audio.limiter;
+        self.limiter
+            .set_params(self.sample_rate, audio.limiter_ceiling_db, audio.limiter_release_ms);
+        let gain = VOLUMES.gain(&config, self.ip);
+        let delay_ms = DELAYS.delay_ms(&config, self.ip);
+        match self.generation {
+            // start at the right volume and delay instead of fading in
+            None => {
+                self.gain.reset(gain);
+                self.delay.set_delay_ms(delay_ms, true);
+            }
+            Some(_) => {
+                self.gain.set_target(gain);
+                self.delay.set_delay_ms(delay_ms, false);
+            }
+        }
+        self.generation = Some(generation);
+    }
+}
@@ -0,0 +1,90 @@
+use std::io::{self, BufRead, Read, Write};
+
+/// largest request body that is accepted
+const MAX_BODY: u64 = 64 * 1024;
+
+/// a parsed http request
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// read the request line, the headers and the body (if it has a `Content-Length`)
+    pub fn read<R: BufRead>(reader: &mut R) -> io::Result<Self> {
+        let mut lines = reader.by_ref().lines();
+        let request_line = lines
+            .next()
+            .unwrap_or_else(|| Err(io::ErrorKind::UnexpectedEof.into()))?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or("/").to_string();
+
+        let mut headers = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        let mut request = Self {
+            method,
+            path,
+            headers,
+            body: Vec::new(),
+        };
+        let length = match request.header("Content-Length") {
+            Some(length) => length
+                .parse::<u64>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid content length"))?,
+            None => 0,
+        };
+        if length > MAX_BODY {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request body too large"));
+        }
+        reader.take(length).read_to_end(&mut request.body)?;
+        Ok(request)
+    }
+
+    /// value of the first header with the given name (case insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// write a complete http response with a body
+pub fn write_response<W: Write>(
+    writer: &mut W,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {status}\r\nConnection: close\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )?;
+    writer.write_all(body)?;
+    writer.flush()
+}
+
+/// write_chunk - write `data` as a single chunk of a chunked transfer encoded body
+///
+/// the chunk size is formatted straight into the writer, so this
+/// does not allocate. the writer is flushed after every chunk
+pub fn write_chunk<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    write!(writer, "{:x}\r\n", data.len())?;
+    writer.write_all(data)?;
+    writer.write_all(b"\r\n")?;
+    writer.flush()
+}
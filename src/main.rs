@@ -1,60 +1,228 @@
 // #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // to suppress console with debug output for release builds
-use crate::{audio::capture::start_audio_capture, config::Config, priority::raise_priority};
+use crate::{
+    audio::{
+        capture::{capture_output_audio, start_audio_capture},
+        detector::SilenceDetector,
+        level::{start_level_monitor_thread, LevelMonitor},
+        ring::SampleRing,
+        silence::SilenceInjector,
+    },
+    dsp::{delay::DelayControl, eq::EqControl, gain::VolumeControl},
+    cli::{Args, Command},
+    config::{
+        watch::{start_config_watcher, Reload},
+        Config,
+    },
+    priority::raise_priority,
+    renderer::{autoplay::start_autoplay_thread, events::start_event_thread, sync_volumes},
+    stream::{sync::SyncGroup, ClientStats},
+};
 
-use audio::devices::Device;
-use cpal::traits::HostTrait;
-use crossbeam_channel::Sender;
-use log::{info, LevelFilter, debug};
+use audio::devices::{find_device, Device};
+use cpal::{
+    traits::{HostTrait, StreamTrait},
+    Stream,
+};
+use log::{info, LevelFilter, debug, error, warn};
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
-use std::{thread, collections::HashMap, net::IpAddr};
+use std::{thread, collections::HashMap, net::IpAddr, sync::Arc};
 
+pub mod api;
 pub mod audio;
+pub mod cli;
 pub mod config;
+pub mod dsp;
+pub mod http;
 pub mod network;
 pub mod priority;
+pub mod renderer;
 pub mod server;
+pub mod stream;
 
 pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
 
-pub static CLIENTS: Lazy<RwLock<HashMap<IpAddr, Sender<Vec<f32>>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
-pub static CONFIG: Lazy<RwLock<Config>> = Lazy::new(|| RwLock::new(Config::load()));
+/// number of samples kept in the shared sample ring (~2.7s of 48kHz stereo audio)
+pub const RING_CAPACITY: usize = 1 << 18;
+
+pub static CLIENTS: Lazy<RwLock<HashMap<IpAddr, Arc<ClientStats>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+pub static RING: Lazy<SampleRing> = Lazy::new(|| SampleRing::new(RING_CAPACITY));
+pub static LEVELS: Lazy<LevelMonitor> = Lazy::new(LevelMonitor::new);
+pub static SILENCE_DETECTOR: Lazy<SilenceDetector> =
+    Lazy::new(|| SilenceDetector::new(CONFIG.read().app.silence_threshold));
+pub static VOLUMES: Lazy<VolumeControl> = Lazy::new(VolumeControl::new);
+pub static EQUALIZERS: Lazy<EqControl> = Lazy::new(EqControl::new);
+pub static DELAYS: Lazy<DelayControl> = Lazy::new(DelayControl::new);
+pub static SYNC_GROUP: Lazy<SyncGroup> = Lazy::new(SyncGroup::new);
+pub static CONFIG: Lazy<RwLock<Config>> = Lazy::new(|| RwLock::new(Config::default()));
 
 /// Sonar
 ///
 /// - setup and start audio capture
 /// - start the streaming webserver
 fn main() {
-    let log_level = if cfg!(debug_assertions) {
-        LevelFilter::Debug
-    } else {
-        CONFIG.read().app.log_level
-    };
-
+    // the log level is lowered once the config is loaded
     env_logger::builder()
-        .filter_level(log_level)
+        .filter_level(LevelFilter::Trace)
         .init();
+    log::set_max_level(LevelFilter::Info);
+
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{}", cli::usage());
+            std::process::exit(2);
+        }
+    };
+
+    // defaults < config file < environment < command line
+    let mut overrides = config::overrides::from_env();
+    overrides.extend(args.overrides.iter().cloned());
+
+    let config_path = Config::locate(args.config.as_deref());
+    info!("using config '{}'", config_path.display());
+    let read_only = args.read_only || args.command == Some(Command::ConfigShow);
+    match Config::load(&config_path, read_only, &overrides) {
+        Ok(config) => *CONFIG.write() = config,
+        Err(e) => {
+            error!("invalid config: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    if args.command == Some(Command::ConfigShow) {
+        match CONFIG.read().to_toml() {
+            Ok(toml) => print!("{toml}"),
+            Err(e) => error!("could not print config: {e}"),
+        }
+        return;
+    }
+
+    log::set_max_level(CONFIG.read().app.log_level());
 
     info!("{} (v{})", APP_NAME, APP_VERSION);
     debug!("Config: {:?}", CONFIG.read());
 
     // first initialize cpal audio to prevent
     // COM reinitialize panic on Windows
-    let audio_device = cpal::default_host()
-        .default_output_device()
-        .map(Device::Output)
-        .expect("No default audio device found!");
+    let audio_device = select_audio_device();
 
     // raise process priority a bit to prevent
     // audio stuttering under cpu load
     raise_priority();
 
+    // allocate the sample ring up front, the capture
+    // callback must not allocate on first access
+    Lazy::force(&RING);
+    Lazy::force(&SILENCE_DETECTOR);
+
     // start the capture of the system audio
     // this variable needs to be keept in scope
     // otherwise the audio capture would stop
-    let _stream = start_audio_capture(&audio_device);
+    let mut capture = Some(Capture {
+        _stream: start_audio_capture(&audio_device),
+        _silence_injector: start_silence_injector(&audio_device),
+    });
+
+    // measure the levels of the captured audio,
+    // `--vu` shows them in the terminal
+    start_level_monitor_thread(&LEVELS, &RING, args.vu_meter);
+
+    // stop and start the renderer when the pc goes silent
+    start_autoplay_thread();
+
+    // follow the state of the renderers through their events
+    start_event_thread();
 
     // start the http webserver
-    thread::spawn(server::start_server).join().unwrap();
+    thread::spawn(server::start_server);
+
+    // apply config changes, the capture stream has to be
+    // restarted on this thread
+    for reload in start_config_watcher(config_path, overrides).iter() {
+        apply_reload(&reload, &mut capture);
+    }
+}
+
+/// the running audio capture, stops when dropped
+struct Capture {
+    _stream: Stream,
+    _silence_injector: Option<SilenceInjector>,
+}
+
+/// keep the capture stream running while nothing is playing,
+/// the injector is stopped when it goes out of scope
+fn start_silence_injector(device: &Device) -> Option<SilenceInjector> {
+    if !CONFIG.read().app.inject_silence {
+        return None;
+    }
+    SilenceInjector::start(device)
+        .map_err(|e| warn!("could not start the silence injector: {e}"))
+        .ok()
+}
+
+/// apply the parts of a reloaded config that are not read live
+fn apply_reload(reload: &Reload, capture: &mut Option<Capture>) {
+    let config = CONFIG.read();
+    log::set_max_level(config.app.log_level());
+    SILENCE_DETECTOR.set_threshold(config.app.silence_threshold);
+    drop(config);
+    dsp::settings_changed();
+
+    if reload.affects("device") || reload.affects("app.latency") || reload.affects("app.inject_silence") {
+        info!("restarting audio capture");
+        // stop the old stream first, both would write into the ring
+        capture.take();
+        let device = select_audio_device();
+        *capture = match capture_output_audio(&device).map(|stream| stream.play().map(|_| stream)) {
+            Some(Ok(stream)) => Some(Capture {
+                _stream: stream,
+                _silence_injector: start_silence_injector(&device),
+            }),
+            Some(Err(e)) => {
+                error!("could not restart audio capture: {e}");
+                None
+            }
+            None => {
+                error!("could not restart audio capture");
+                None
+            }
+        };
+    }
+
+    if reload.affects("server.network") || reload.affects("server.port") {
+        server::rebind();
+    }
+
+    if reload.affects("renderers") {
+        sync_volumes();
+    }
+
+    if ["audio", "app.latency", "app.buffer_ms", "app.capture_timeout", "renderers"]
+        .into_iter()
+        .any(|key| reload.affects(key))
+        && !CLIENTS.read().is_empty()
+    {
+        info!("stream settings changed, they apply once the renderer reconnects");
+    }
+
+    let restart = reload.restart_required();
+    if !restart.is_empty() {
+        warn!("{} only take effect after a restart", restart.join(", "));
+    }
+}
+
+/// the configured capture device, or the default output device
+fn select_audio_device() -> Device {
+    if let Some(device) = &CONFIG.read().device {
+        match find_device(&device.name, device.index) {
+            Some(found) => return found,
+            None => warn!("audio device '{}' not found, using the default output device", device.name),
+        }
+    }
+    cpal::default_host()
+        .default_output_device()
+        .map(Device::Output)
+        .expect("No default audio device found!")
 }
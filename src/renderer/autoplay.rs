@@ -0,0 +1,122 @@
+use std::{thread, time::Duration};
+
+use log::{info, warn};
+
+use crate::{CLIENTS, CONFIG, SILENCE_DETECTOR};
+
+use super::{events, sonos, start_group, stream_url, Renderer, RendererError};
+
+/// how often the silence detector is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// a renderer sonar has stopped and has to start again
+struct Stopped {
+    renderer: Renderer,
+    /// whether its events reported that it stopped, until then it may still
+    /// report the state from before it was stopped
+    confirmed: bool,
+}
+
+/// start_autoplay_thread - stop the renderers while the pc is silent
+///
+/// when the captured audio has been silent for `app.auto_stop` seconds the
+/// renderers are stopped, which frees them for other sources. once audio is
+/// captured again, they are pointed back to sonar and started together,
+/// unless something else is playing on them by now. a renderer that is
+/// started with another source in the meantime is left alone
+pub fn start_autoplay_thread() {
+    thread::Builder::new()
+        .name("autoplay".into())
+        .spawn(run_autoplay)
+        .unwrap();
+}
+
+fn run_autoplay() {
+    let mut stopped: Vec<Stopped> = Vec::new();
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let (renderers, stop_after) = {
+            let config = CONFIG.read();
+            SILENCE_DETECTOR.set_threshold(config.app.silence_threshold);
+            (
+                config.renderers().map(Renderer::new).collect::<Vec<_>>(),
+                Duration::from_secs(config.app.auto_stop),
+            )
+        };
+        if renderers.is_empty() || stop_after.is_zero() {
+            stopped.clear();
+            continue;
+        }
+        stopped.retain_mut(|stopped| !started_elsewhere(stopped));
+
+        let silent_for = SILENCE_DETECTOR.silent_for();
+        if stopped.is_empty() && silent_for >= stop_after {
+            // only stop the renderers that are actually playing sonar's stream
+            let playing: Vec<Renderer> = {
+                let clients = CLIENTS.read();
+                renderers
+                    .into_iter()
+                    .filter(|renderer| clients.contains_key(&renderer.addr.ip()))
+                    .collect()
+            };
+            for renderer in playing {
+                info!("silent for {silent_for:?}, stopping renderer '{}'", renderer.name);
+                match sonos::target(&renderer).and_then(|target| target.stop()) {
+                    Ok(()) => stopped.push(Stopped {
+                        renderer,
+                        confirmed: false,
+                    }),
+                    Err(e) => warn!("could not stop renderer '{}': {e}", renderer.name),
+                }
+            }
+        } else if !stopped.is_empty() && silent_for < POLL_INTERVAL {
+            let url = stream_url();
+            let resume: Vec<Renderer> = stopped
+                .drain(..)
+                .map(|stopped| stopped.renderer)
+                .filter(|renderer| match current_uri(renderer) {
+                    Ok(Some(uri)) if uri != url => {
+                        info!("renderer '{}' is playing another source, not starting it", renderer.name);
+                        false
+                    }
+                    _ => true,
+                })
+                .collect();
+            for renderer in &resume {
+                info!("audio resumed, starting renderer '{}'", renderer.name);
+            }
+            // started together, so multiroom groups stay in sync
+            for (renderer, e) in start_group(&resume) {
+                warn!("could not start renderer '{}': {e}", renderer.name);
+            }
+        }
+    }
+}
+
+/// whether a renderer sonar stopped was started again by someone else, e.g. in the Sonos app
+fn started_elsewhere(stopped: &mut Stopped) -> bool {
+    let renderer = &stopped.renderer;
+    let Some(transport_state) = events::state(renderer.addr.ip()).and_then(|state| state.transport_state) else {
+        return false;
+    };
+    let playing = matches!(transport_state.as_str(), "PLAYING" | "TRANSITIONING");
+    if !playing {
+        stopped.confirmed = true;
+        return false;
+    }
+    if stopped.confirmed {
+        info!("renderer '{}' was started with another source, not starting it again", renderer.name);
+    }
+    stopped.confirmed
+}
+
+/// the uri a renderer is set to, taken from its events if sonar is subscribed
+fn current_uri(renderer: &Renderer) -> Result<Option<String>, RendererError> {
+    // the members of a sonos group are set to their coordinator
+    let reported = events::state(renderer.addr.ip()).filter(|_| !renderer.group_coordinator);
+    match reported.and_then(|state| state.uri) {
+        Some(uri) => Ok(Some(uri)),
+        None => sonos::target(renderer).and_then(|target| target.current_uri()),
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::Serialize;
+
+use crate::{
+    dsp::gain::Volume,
+    http::{write_response, Request},
+    CONFIG, VOLUMES,
+};
+
+use super::{
+    local_url,
+    soap::{self, Service},
+    volume_db, Renderer, RendererError, AV_TRANSPORT, RENDERING_CONTROL,
+};
+
+/// path of the event callback on sonar's http server,
+/// followed by `/<renderer ip>/<service name>`
+pub const EVENT_PATH: &str = "/upnp/event";
+
+/// the services sonar subscribes to
+const SERVICES: [Service; 2] = [AV_TRANSPORT, RENDERING_CONTROL];
+/// requested length of a subscription, it is renewed halfway through
+const SUBSCRIPTION_SECS: u64 = 300;
+/// how often the subscriptions are checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// wait before subscribing again after a failed attempt
+const RETRY_AFTER: Duration = Duration::from_secs(60);
+/// how long an event waits for the subscription it belongs to, the first
+/// event can arrive before the response to the subscription
+const SUBSCRIBING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// the last reported state of every renderer sonar is subscribed to
+static STATES: Lazy<RwLock<HashMap<IpAddr, RendererState>>> = Lazy::new(Default::default);
+
+/// a renderer's address and the name of one of its services
+type ServiceKey = (IpAddr, &'static str);
+
+/// the ids of the live subscriptions, `None` while subscribing
+static SIDS: Lazy<RwLock<HashMap<ServiceKey, Option<String>>>> = Lazy::new(Default::default);
+
+/// RendererState - the state of a renderer, as reported by its events
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RendererState {
+    /// e.g. `PLAYING`, `PAUSED_PLAYBACK` or `STOPPED`
+    pub transport_state: Option<String>,
+    /// the uri the renderer is set to
+    pub uri: Option<String>,
+    /// the renderer's own volume, from 0 to 100
+    pub volume: Option<u8>,
+    pub mute: Option<bool>,
+}
+
+/// state - the last reported state of a renderer, `None` while sonar is not subscribed
+pub fn state(ip: IpAddr) -> Option<RendererState> {
+    STATES.read().get(&ip).cloned()
+}
+
+/// a GENA subscription to the events of a service
+struct Subscription {
+    addr: SocketAddr,
+    service: Service,
+    callback: String,
+    /// the subscription id, `None` while not subscribed
+    sid: Option<String>,
+    /// when the subscription has to be renewed or retried
+    due: Instant,
+}
+
+impl Subscription {
+    fn subscribe(&mut self) -> Result<(), RendererError> {
+        SIDS.write().insert(self.key(), None);
+        let request = format!(
+            concat!(
+                "SUBSCRIBE {path} HTTP/1.1\r\n",
+                "Host: {addr}\r\n",
+                "CALLBACK: <{callback}>\r\n",
+                "NT: upnp:event\r\n",
+                "TIMEOUT: Second-{timeout}\r\n",
+                "Content-Length: 0\r\n",
+                "Connection: close\r\n",
+                "\r\n"
+            ),
+            path = self.service.event_path,
+            addr = self.addr,
+            callback = self.callback,
+            timeout = SUBSCRIPTION_SECS
+        );
+        let response = self.send("SUBSCRIBE", &request)?;
+        let sid = response.header("SID").ok_or(RendererError::InvalidResponse)?.to_string();
+        SIDS.write().insert(self.key(), Some(sid.clone()));
+        self.sid = Some(sid);
+        self.due = Instant::now() + granted(&response) / 2;
+        Ok(())
+    }
+
+    /// extend the subscription, the renderer forgets it otherwise
+    fn renew(&mut self) -> Result<(), RendererError> {
+        let Some(sid) = &self.sid else {
+            return self.subscribe();
+        };
+        let request = format!(
+            concat!(
+                "SUBSCRIBE {path} HTTP/1.1\r\n",
+                "Host: {addr}\r\n",
+                "SID: {sid}\r\n",
+                "TIMEOUT: Second-{timeout}\r\n",
+                "Content-Length: 0\r\n",
+                "Connection: close\r\n",
+                "\r\n"
+            ),
+            path = self.service.event_path,
+            addr = self.addr,
+            sid = sid,
+            timeout = SUBSCRIPTION_SECS
+        );
+        let response = self.send("RENEW", &request)?;
+        self.due = Instant::now() + granted(&response) / 2;
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self) {
+        SIDS.write().remove(&self.key());
+        let Some(sid) = self.sid.take() else {
+            return;
+        };
+        let request = format!(
+            concat!(
+                "UNSUBSCRIBE {path} HTTP/1.1\r\n",
+                "Host: {addr}\r\n",
+                "SID: {sid}\r\n",
+                "Content-Length: 0\r\n",
+                "Connection: close\r\n",
+                "\r\n"
+            ),
+            path = self.service.event_path,
+            addr = self.addr,
+            sid = sid
+        );
+        // the renderer drops the subscription on its own once it expires
+        if let Err(e) = self.send("UNSUBSCRIBE", &request) {
+            debug!("could not unsubscribe from '{}': {e}", self.addr);
+        }
+    }
+
+    fn key(&self) -> ServiceKey {
+        (self.addr.ip(), self.service.name())
+    }
+
+    fn send(&self, method: &str, request: &str) -> Result<soap::Response, RendererError> {
+        debug!("GENA {method} {} -> {}", self.service.name(), self.addr);
+        let response = soap::send(self.addr, request)?;
+        match response.status {
+            200 => Ok(response),
+            status => Err(RendererError::Soap {
+                action: method.to_string(),
+                status,
+                code: None,
+            }),
+        }
+    }
+}
+
+/// the length of the subscription the renderer granted, `TIMEOUT: Second-300`
+fn granted(response: &soap::Response) -> Duration {
+    let secs = response
+        .header("TIMEOUT")
+        .and_then(|timeout| timeout.strip_prefix("Second-"))
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(SUBSCRIPTION_SECS);
+    Duration::from_secs(secs.max(2))
+}
+
+/// start_event_thread - subscribe to the events of the configured renderers
+///
+/// the renderers report changes of their transport state, uri, volume and
+/// mute right away, instead of sonar having to ask for them. the
+/// subscriptions follow config changes and are renewed before they expire
+pub fn start_event_thread() {
+    thread::Builder::new()
+        .name("renderer_events".into())
+        .spawn(run_subscriptions)
+        .unwrap();
+}
+
+fn run_subscriptions() {
+    let mut subscriptions: Vec<Subscription> = Vec::new();
+    loop {
+        let renderers: Vec<Renderer> = CONFIG.read().renderers().map(Renderer::new).collect();
+
+        // drop the subscriptions of removed renderers and those with an outdated
+        // callback, e.g. after the server moved to another port
+        subscriptions.retain_mut(|subscription| {
+            let keep = renderers.iter().any(|renderer| renderer.addr == subscription.addr)
+                && subscription.callback == callback(subscription.addr.ip(), &subscription.service);
+            if !keep {
+                subscription.unsubscribe();
+                STATES.write().remove(&subscription.addr.ip());
+            }
+            keep
+        });
+        for renderer in &renderers {
+            for service in SERVICES {
+                let subscribed = subscriptions
+                    .iter()
+                    .any(|s| s.addr == renderer.addr && s.service.urn == service.urn);
+                if !subscribed {
+                    subscriptions.push(Subscription {
+                        addr: renderer.addr,
+                        service,
+                        callback: callback(renderer.addr.ip(), &service),
+                        sid: None,
+                        due: Instant::now(),
+                    });
+                }
+            }
+        }
+
+        let now = Instant::now();
+        for subscription in subscriptions.iter_mut().filter(|s| s.due <= now) {
+            let subscribed = subscription.sid.is_some();
+            if let Err(e) = subscription.renew() {
+                debug!(
+                    "could not subscribe to the {} events of '{}': {e}",
+                    subscription.service.name(),
+                    subscription.addr
+                );
+                subscription.sid = None;
+                SIDS.write().remove(&subscription.key());
+                // a lost subscription is renewed right away, a failed one later
+                subscription.due = match subscribed {
+                    true => now,
+                    false => now + RETRY_AFTER,
+                };
+                STATES.write().remove(&subscription.addr.ip());
+            }
+        }
+        thread::sleep(CHECK_INTERVAL);
+    }
+}
+
+/// the callback url the events of a renderer's service are sent to
+fn callback(ip: IpAddr, service: &Service) -> String {
+    local_url(&format!("{EVENT_PATH}/{ip}/{}", service.name()))
+}
+
+/// handle_notify - handle an event sent to the callback of a subscription
+///
+/// only events of a live subscription are accepted, sent by the renderer itself
+pub fn handle_notify(mut stream: &TcpStream, request: &Request) -> io::Result<()> {
+    let source = request.path[EVENT_PATH.len()..]
+        .trim_start_matches('/')
+        .split_once('/')
+        .and_then(|(ip, service)| Some((ip.parse::<IpAddr>().ok()?, service)));
+    let renderer = source.and_then(|(ip, service)| {
+        let config = CONFIG.read();
+        let renderer = config.renderer_for(ip)?;
+        Some((ip, service, renderer.name.clone(), renderer.volume_sync))
+    });
+    let peer = stream.peer_addr()?.ip();
+    let valid = renderer
+        .as_ref()
+        .is_some_and(|(ip, service, ..)| *ip == peer && is_subscribed(*ip, service, request.header("SID")));
+    let Some((ip, service, name, volume_sync)) = renderer.filter(|_| valid) else {
+        debug!("rejecting event from '{peer}' for '{}'", request.path);
+        // tells the renderer to cancel the subscription
+        return write_response(&mut stream, "412 Precondition Failed", "text/plain", b"");
+    };
+    write_response(&mut stream, "200 OK", "text/plain", b"")?;
+
+    let body = String::from_utf8_lossy(&request.body);
+    let Some(event) = soap::value(&body, "LastChange") else {
+        return Ok(());
+    };
+    let (old, new) = {
+        let mut states = STATES.write();
+        let state = states.entry(ip).or_default();
+        let old = state.clone();
+        if service == AV_TRANSPORT.name() {
+            parse_av_transport(&event, state);
+        } else if service == RENDERING_CONTROL.name() {
+            parse_rendering_control(&event, state);
+        }
+        (old, state.clone())
+    };
+
+    if new.transport_state != old.transport_state {
+        if let Some(transport_state) = &new.transport_state {
+            info!("renderer '{name}' is {}", transport_state.to_lowercase().replace('_', " "));
+        }
+    }
+    // the volume was changed on the renderer, e.g. in the Sonos app
+    if volume_sync && (new.volume, new.mute) != (old.volume, old.mute) {
+        if let (Some(volume), Some(mute)) = (new.volume, new.mute) {
+            debug!("renderer '{name}' changed its volume to {volume}");
+            VOLUMES.set_client(
+                ip,
+                Volume {
+                    gain_db: volume_db(volume),
+                    mute,
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+/// whether the sid belongs to a live subscription to the renderer's service
+///
+/// waits for a subscription that is still being set up, the renderer
+/// may send its first event before it answered the subscription
+fn is_subscribed(ip: IpAddr, service: &str, sid: Option<&str>) -> bool {
+    let Some(sid) = sid else {
+        return false;
+    };
+    let deadline = Instant::now() + SUBSCRIBING_TIMEOUT;
+    loop {
+        let subscribing = {
+            let sids = SIDS.read();
+            match sids.iter().find(|((addr, name), _)| *addr == ip && *name == service) {
+                Some((_, Some(live))) => return live == sid,
+                Some((_, None)) => true,
+                None => false,
+            }
+        };
+        if !subscribing || Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// the `val` attribute of the first element with this name
+fn event_value(event: &str, tag: &str) -> Option<String> {
+    soap::elements(event, tag)
+        .first()
+        .and_then(|element| element.attribute("val"))
+}
+
+fn parse_av_transport(event: &str, state: &mut RendererState) {
+    if let Some(transport_state) = event_value(event, "TransportState") {
+        state.transport_state = Some(transport_state);
+    }
+    if let Some(uri) = event_value(event, "AVTransportURI") {
+        state.uri = Some(uri).filter(|uri| !uri.is_empty());
+    }
+}
+
+fn parse_rendering_control(event: &str, state: &mut RendererState) {
+    let master = |tag| {
+        soap::elements(event, tag)
+            .into_iter()
+            .find(|element| element.attribute("channel").as_deref() == Some("Master"))
+            .and_then(|element| element.attribute("val"))
+    };
+    if let Some(volume) = master("Volume").and_then(|volume| volume.parse().ok()) {
+        state.volume = Some(volume);
+    }
+    if let Some(mute) = master("Mute") {
+        state.mute = Some(matches!(mute.as_str(), "1" | "true"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// AVTransport NOTIFY body of a Sonos speaker playing sonar's stream, the
+    /// metadata is escaped once more inside the escaped LastChange
+    const AV_TRANSPORT_NOTIFY: &str = concat!(
+        r#"<e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0"><e:property><LastChange>"#,
+        r#"&lt;Event xmlns=&quot;urn:schemas-upnp-org:metadata-1-0/AVT/&quot; xmlns:r=&quot;urn:schemas-rinconnetworks-com:metadata-1-0/&quot;&gt;"#,
+        r#"&lt;InstanceID val=&quot;0&quot;&gt;"#,
+        r#"&lt;TransportState val=&quot;PLAYING&quot;/&gt;"#,
+        r#"&lt;CurrentPlayMode val=&quot;NORMAL&quot;/&gt;"#,
+        r#"&lt;NumberOfTracks val=&quot;1&quot;/&gt;"#,
+        r#"&lt;CurrentTrackURI val=&quot;http://192.168.1.20:5901/stream/swyh.wav&quot;/&gt;"#,
+        r#"&lt;CurrentTrackMetaData val=&quot;&amp;lt;DIDL-Lite xmlns:dc=&amp;quot;http://purl.org/dc/elements/1.1/&amp;quot; "#,
+        r#"xmlns=&amp;quot;urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/&amp;quot;&amp;gt;&amp;lt;item id=&amp;quot;-1&amp;quot; parentID=&amp;quot;-1&amp;quot;&amp;gt;"#,
+        r#"&amp;lt;dc:title&amp;gt;Sonar &amp;amp;amp; &amp;amp;quot;friends&amp;amp;quot;&amp;lt;/dc:title&amp;gt;"#,
+        r#"&amp;lt;res protocolInfo=&amp;quot;http-get:*:audio/wav:*&amp;quot;&amp;gt;http://192.168.1.20:5901/stream/swyh.wav&amp;lt;/res&amp;gt;"#,
+        r#"&amp;lt;/item&amp;gt;&amp;lt;/DIDL-Lite&amp;gt;&quot;/&gt;"#,
+        r#"&lt;r:NextTrackURI val=&quot;&quot;/&gt;"#,
+        r#"&lt;NextAVTransportURI val=&quot;x-rincon-queue:RINCON_000E58A0B1C201400#0&quot;/&gt;"#,
+        r#"&lt;AVTransportURI val=&quot;http://192.168.1.20:5901/stream/swyh.wav&quot;/&gt;"#,
+        r#"&lt;r:EnqueuedTransportURI val=&quot;&quot;/&gt;"#,
+        r#"&lt;/InstanceID&gt;&lt;/Event&gt;"#,
+        r#"</LastChange></e:property></e:propertyset>"#
+    );
+
+    /// RenderingControl NOTIFY body, the front channels are reported before Master
+    const RENDERING_CONTROL_NOTIFY: &str = concat!(
+        r#"<e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0"><e:property><LastChange>"#,
+        r#"&lt;Event xmlns=&quot;urn:schemas-upnp-org:metadata-1-0/RCS/&quot;&gt;&lt;InstanceID val=&quot;0&quot;&gt;"#,
+        r#"&lt;Volume channel=&quot;LF&quot; val=&quot;100&quot;/&gt;"#,
+        r#"&lt;Volume channel=&quot;RF&quot; val=&quot;100&quot;/&gt;"#,
+        r#"&lt;Volume channel=&quot;Master&quot; val=&quot;23&quot;/&gt;"#,
+        r#"&lt;Mute channel=&quot;LF&quot; val=&quot;0&quot;/&gt;"#,
+        r#"&lt;Mute channel=&quot;Master&quot; val=&quot;1&quot;/&gt;"#,
+        r#"&lt;Bass val=&quot;0&quot;/&gt;&lt;Treble val=&quot;0&quot;/&gt;"#,
+        r#"&lt;Loudness channel=&quot;Master&quot; val=&quot;1&quot;/&gt;"#,
+        r#"&lt;/InstanceID&gt;&lt;/Event&gt;"#,
+        r#"</LastChange></e:property></e:propertyset>"#
+    );
+
+    #[test]
+    fn av_transport_last_change() {
+        let event = soap::value(AV_TRANSPORT_NOTIFY, "LastChange").unwrap();
+        let mut state = RendererState::default();
+        parse_av_transport(&event, &mut state);
+        assert_eq!(state.transport_state.as_deref(), Some("PLAYING"));
+        assert_eq!(state.uri.as_deref(), Some("http://192.168.1.20:5901/stream/swyh.wav"));
+        assert_eq!(state.volume, None);
+        let metadata = event_value(&event, "CurrentTrackMetaData").unwrap();
+        assert!(metadata.contains("<dc:title>Sonar &amp; &quot;friends&quot;</dc:title>"), "{metadata}");
+
+        // an event only carries what changed
+        let stopped = "<Event><InstanceID val=\"0\"><TransportState val=\"STOPPED\"/></InstanceID></Event>";
+        parse_av_transport(stopped, &mut state);
+        assert_eq!(state.transport_state.as_deref(), Some("STOPPED"));
+        assert_eq!(state.uri.as_deref(), Some("http://192.168.1.20:5901/stream/swyh.wav"));
+    }
+
+    #[test]
+    fn rendering_control_last_change() {
+        let event = soap::value(RENDERING_CONTROL_NOTIFY, "LastChange").unwrap();
+        let mut state = RendererState::default();
+        parse_rendering_control(&event, &mut state);
+        assert_eq!(state.volume, Some(23));
+        assert_eq!(state.mute, Some(true));
+        assert_eq!(state.transport_state, None);
+    }
+
+    #[test]
+    fn events_need_live_subscription() {
+        let ip: IpAddr = "192.0.2.10".parse().unwrap();
+        let service = AV_TRANSPORT.name();
+        assert!(!is_subscribed(ip, service, Some("uuid:1")));
+
+        SIDS.write().insert((ip, service), Some("uuid:1".to_string()));
+        assert!(is_subscribed(ip, service, Some("uuid:1")));
+        assert!(!is_subscribed(ip, service, Some("uuid:2")));
+        assert!(!is_subscribed(ip, service, None));
+        assert!(!is_subscribed(ip, RENDERING_CONTROL.name(), Some("uuid:1")));
+    }
+
+    #[test]
+    fn events_wait_for_subscription() {
+        let ip: IpAddr = "192.0.2.11".parse().unwrap();
+        let service = RENDERING_CONTROL.name();
+        SIDS.write().insert((ip, service), None);
+        let subscribed = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            SIDS.write().insert((ip, service), Some("uuid:3".to_string()));
+        });
+        assert!(is_subscribed(ip, service, Some("uuid:3")));
+        subscribed.join().unwrap();
+    }
+}
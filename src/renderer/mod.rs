@@ -0,0 +1,312 @@
+pub mod autoplay;
+pub mod events;
+pub mod soap;
+pub mod sonos;
+
+use std::{
+    error::Error,
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    sync::Barrier,
+    thread,
+};
+
+use log::warn;
+
+use crate::{
+    audio::format::StreamingFormat,
+    config::{RendererConfig, GAIN_RANGE_DB},
+    dsp::gain::{db_to_gain, Volume},
+    network::get_local_addr,
+    CONFIG, RING, SYNC_GROUP, VOLUMES,
+};
+
+use self::soap::{escape, Service};
+
+/// port of the UPnP services of Sonos speakers
+pub const SONOS_PORT: u16 = 1400;
+
+/// path of the audio stream on sonar's http server
+pub const STREAM_PATH: &str = "/stream.wav";
+
+pub const AV_TRANSPORT: Service = Service {
+    urn: "urn:schemas-upnp-org:service:AVTransport:1",
+    control_path: "/MediaRenderer/AVTransport/Control",
+    event_path: "/MediaRenderer/AVTransport/Event",
+};
+
+pub const RENDERING_CONTROL: Service = Service {
+    urn: "urn:schemas-upnp-org:service:RenderingControl:1",
+    control_path: "/MediaRenderer/RenderingControl/Control",
+    event_path: "/MediaRenderer/RenderingControl/Event",
+};
+
+/// errors while controlling a renderer
+#[derive(Debug)]
+pub enum RendererError {
+    Io(io::Error),
+    /// the renderer answered with an error
+    Soap {
+        action: String,
+        status: u16,
+        code: Option<String>,
+    },
+    InvalidResponse,
+    /// the speaker is not part of any Sonos zone group
+    UnknownSpeaker(IpAddr),
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::Io(e) => write!(f, "{e}"),
+            RendererError::Soap {
+                action,
+                status,
+                code,
+            } => write!(
+                f,
+                "{action} failed with status {status} (error code {})",
+                code.as_deref().unwrap_or("unknown")
+            ),
+            RendererError::InvalidResponse => write!(f, "invalid response"),
+            RendererError::UnknownSpeaker(ip) => write!(f, "'{ip}' is not in any sonos zone group"),
+        }
+    }
+}
+
+impl Error for RendererError {}
+
+impl From<io::Error> for RendererError {
+    fn from(e: io::Error) -> Self {
+        RendererError::Io(e)
+    }
+}
+
+/// Renderer - a UPnP media renderer (Sonos speaker) controlled by sonar
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    pub name: String,
+    pub addr: SocketAddr,
+    /// control the coordinator of the speaker's Sonos group instead
+    pub group_coordinator: bool,
+    /// speakers grouped with this renderer when it starts
+    pub group_members: Vec<IpAddr>,
+}
+
+impl Renderer {
+    pub fn new(config: &RendererConfig) -> Self {
+        Self {
+            name: config.name.clone(),
+            addr: SocketAddr::new(config.ip_addr, SONOS_PORT),
+            group_coordinator: config.group_coordinator,
+            group_members: config.group_members.clone(),
+        }
+    }
+
+    /// let the renderer play the given stream url (does not start playback)
+    pub fn set_uri(&self, uri: &str) -> Result<(), RendererError> {
+        let metadata = didl_metadata(uri, &self.mime_type());
+        soap::call(
+            self.addr,
+            &AV_TRANSPORT,
+            "SetAVTransportURI",
+            &[
+                ("InstanceID", "0"),
+                ("CurrentURI", uri),
+                ("CurrentURIMetaData", &metadata),
+            ],
+        )
+        .map(|_| ())
+    }
+
+    /// the mime type of the stream sonar sends to the renderer
+    fn mime_type(&self) -> String {
+        // a group coordinator gets the stream of the configured renderer
+        let ip = sonos::renderer_ip(self.addr.ip());
+        match CONFIG.read().stream_format(ip) {
+            (StreamingFormat::Wav, _) => "audio/wav".to_string(),
+            (format, bits_per_sample) => format.mime_type(RING.sample_rate(), RING.channels(), bits_per_sample),
+        }
+    }
+
+    pub fn play(&self) -> Result<(), RendererError> {
+        soap::call(
+            self.addr,
+            &AV_TRANSPORT,
+            "Play",
+            &[("InstanceID", "0"), ("Speed", "1")],
+        )
+        .map(|_| ())
+    }
+
+    pub fn stop(&self) -> Result<(), RendererError> {
+        soap::call(self.addr, &AV_TRANSPORT, "Stop", &[("InstanceID", "0")]).map(|_| ())
+    }
+
+    /// set the renderer's own volume, from 0 to 100
+    pub fn set_volume(&self, volume: u8) -> Result<(), RendererError> {
+        soap::call(
+            self.addr,
+            &RENDERING_CONTROL,
+            "SetVolume",
+            &[
+                ("InstanceID", "0"),
+                ("Channel", "Master"),
+                ("DesiredVolume", &volume.to_string()),
+            ],
+        )
+        .map(|_| ())
+    }
+
+    pub fn set_mute(&self, mute: bool) -> Result<(), RendererError> {
+        soap::call(
+            self.addr,
+            &RENDERING_CONTROL,
+            "SetMute",
+            &[
+                ("InstanceID", "0"),
+                ("Channel", "Master"),
+                ("DesiredMute", if mute { "1" } else { "0" }),
+            ],
+        )
+        .map(|_| ())
+    }
+
+    /// the uri the renderer is currently set to
+    pub fn current_uri(&self) -> Result<Option<String>, RendererError> {
+        let response = soap::call(self.addr, &AV_TRANSPORT, "GetMediaInfo", &[("InstanceID", "0")])?;
+        Ok(soap::value(&response, "CurrentURI").filter(|uri| !uri.is_empty()))
+    }
+}
+
+/// start_group - point all renderers to sonar's stream and start them together
+///
+/// the uris are set first, then all renderers are started at the same time,
+/// so their streams connect together and the multiroom group starts in sync.
+/// renderers of a Sonos group are started on the group's coordinator.
+/// returns the renderers that failed
+pub fn start_group(renderers: &[Renderer]) -> Vec<(&Renderer, RendererError)> {
+    SYNC_GROUP.reset();
+    let url = stream_url();
+    let barrier = Barrier::new(renderers.len());
+    thread::scope(|s| {
+        let handles: Vec<_> = renderers
+            .iter()
+            .map(|renderer| {
+                s.spawn(|| {
+                    let target = sonos::target(renderer).and_then(|target| {
+                        target.set_uri(&url)?;
+                        sonos::join_members(renderer, &target)?;
+                        Ok(target)
+                    });
+                    barrier.wait();
+                    target.and_then(|target| target.play())?;
+                    if let Err(e) = sync_volume(renderer.addr.ip()) {
+                        warn!("could not set the volume of renderer '{}': {e}", renderer.name);
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .zip(renderers)
+            .filter_map(|(handle, renderer)| match handle.join() {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some((renderer, e)),
+                Err(_) => Some((renderer, RendererError::InvalidResponse)),
+            })
+            .collect()
+    })
+}
+
+/// sync_volume - set the volume of a renderer with `volume_sync` to its volume in sonar
+///
+/// the renderer's volume follows sonar's volume of the renderer, 0 dB is
+/// full volume. the global volume is still applied to the samples
+pub fn sync_volume(ip: IpAddr) -> Result<(), RendererError> {
+    let volume = VOLUMES.client(&CONFIG.read(), ip);
+    set_renderer_volume(ip, volume)
+}
+
+/// set_renderer_volume - set the volume of a renderer with `volume_sync`, others are left alone
+pub fn set_renderer_volume(ip: IpAddr, volume: Volume) -> Result<(), RendererError> {
+    let renderer = match CONFIG.read().renderer_for(ip).filter(|renderer| renderer.volume_sync) {
+        Some(renderer) => Renderer::new(renderer),
+        None => return Ok(()),
+    };
+    let target = sonos::target(&renderer)?;
+    target.set_volume(volume_percent(volume.gain_db))?;
+    target.set_mute(volume.mute)
+}
+
+/// sync_volumes - [sync_volume] for all renderers, e.g. after the config changed
+pub fn sync_volumes() {
+    let renderers: Vec<(String, IpAddr)> = CONFIG
+        .read()
+        .renderers()
+        .filter(|renderer| renderer.volume_sync)
+        .map(|renderer| (renderer.name.clone(), renderer.ip_addr))
+        .collect();
+    for (name, ip) in renderers {
+        if let Err(e) = sync_volume(ip) {
+            warn!("could not set the volume of renderer '{name}': {e}");
+        }
+    }
+}
+
+/// the renderer volume for a gain in dB
+pub fn volume_percent(gain_db: f32) -> u8 {
+    (db_to_gain(gain_db).min(1.0) * 100.0).round() as u8
+}
+
+/// the gain in dB for a renderer volume, the reverse of [volume_percent]
+pub fn volume_db(percent: u8) -> f32 {
+    match percent {
+        0 => *GAIN_RANGE_DB.start(),
+        percent => (20.0 * (percent.min(100) as f32 / 100.0).log10()).max(*GAIN_RANGE_DB.start()),
+    }
+}
+
+/// stream_url - the url renderers use to connect to sonar's audio stream
+pub fn stream_url() -> String {
+    local_url(STREAM_PATH)
+}
+
+/// local_url - the url of a path on sonar's http server, as seen by the renderers
+pub fn local_url(path: &str) -> String {
+    let (network, port) = {
+        let config = CONFIG.read();
+        (config.server.network, config.server.port)
+    };
+    // renderers can't reach a loopback or wildcard address
+    let ip = if network.is_loopback() || network.is_unspecified() {
+        get_local_addr().unwrap_or(network)
+    } else {
+        network
+    };
+    match ip {
+        IpAddr::V4(ip) => format!("http://{ip}:{port}{path}"),
+        IpAddr::V6(ip) => format!("http://[{ip}]:{port}{path}"),
+    }
+}
+
+/// DIDL-Lite metadata describing the audio stream
+fn didl_metadata(uri: &str, mime_type: &str) -> String {
+    format!(
+        concat!(
+            r#"<DIDL-Lite xmlns:dc="http://purl.org/dc/elements/1.1/" "#,
+            r#"xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/" "#,
+            r#"xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/">"#,
+            r#"<item id="0" parentID="-1" restricted="1">"#,
+            r#"<dc:title>{title}</dc:title>"#,
+            r#"<upnp:class>object.item.audioItem.musicTrack</upnp:class>"#,
+            r#"<res protocolInfo="http-get:*:{mime_type}:*">{uri}</res>"#,
+            r#"</item></DIDL-Lite>"#
+        ),
+        title = crate::APP_NAME,
+        mime_type = escape(mime_type),
+        uri = escape(uri)
+    )
+}
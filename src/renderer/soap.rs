@@ -0,0 +1,301 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use log::debug;
+
+use super::RendererError;
+
+/// timeout for connecting to and talking with a renderer
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// a UPnP service of a renderer
+#[derive(Debug, Clone, Copy)]
+pub struct Service {
+    /// the service type, e.g. `urn:schemas-upnp-org:service:AVTransport:1`
+    pub urn: &'static str,
+    /// path of the service's control url
+    pub control_path: &'static str,
+    /// path of the service's event subscription url
+    pub event_path: &'static str,
+}
+
+impl Service {
+    /// the name of the service, e.g. `AVTransport`
+    pub fn name(&self) -> &'static str {
+        self.urn.split(':').nth(3).unwrap_or(self.urn)
+    }
+}
+
+/// an http response of a renderer
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    /// value of the first header with the given name (case insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// call - invoke a SOAP action on a UPnP service and return the response body
+///
+/// the argument values are xml escaped
+pub fn call(
+    addr: SocketAddr,
+    service: &Service,
+    action: &str,
+    args: &[(&str, &str)],
+) -> Result<String, RendererError> {
+    let mut arguments = String::new();
+    for (name, value) in args {
+        arguments.push_str(&format!("<{name}>{}</{name}>", escape(value)));
+    }
+    let body = format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" "#,
+            r#"s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+            r#"<s:Body><u:{action} xmlns:u="{urn}">{arguments}</u:{action}></s:Body>"#,
+            r#"</s:Envelope>"#
+        ),
+        action = action,
+        urn = service.urn,
+        arguments = arguments
+    );
+    let request = format!(
+        concat!(
+            "POST {path} HTTP/1.1\r\n",
+            "Host: {addr}\r\n",
+            "Content-Type: text/xml; charset=\"utf-8\"\r\n",
+            "SOAPACTION: \"{urn}#{action}\"\r\n",
+            "Content-Length: {length}\r\n",
+            "Connection: close\r\n",
+            "\r\n",
+            "{body}"
+        ),
+        path = service.control_path,
+        addr = addr,
+        urn = service.urn,
+        action = action,
+        length = body.len(),
+        body = body
+    );
+
+    debug!("SOAP {action} -> {addr}");
+    let response = send(addr, &request)?;
+    match response.status {
+        200 => Ok(response.body),
+        status => Err(RendererError::Soap {
+            action: action.to_string(),
+            status,
+            code: value(&response.body, "errorCode"),
+        }),
+    }
+}
+
+/// send a raw http request and read the response
+pub fn send(addr: SocketAddr, request: &str) -> Result<Response, RendererError> {
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.write_all(request.as_bytes())?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or(RendererError::InvalidResponse)?;
+
+    let mut headers = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    // the connection is closed after the body
+    let mut body = String::new();
+    reader.read_to_string(&mut body)?;
+    Ok(Response { status, headers, body })
+}
+
+/// value - the unescaped text of the first element with the given (local) name
+pub fn value(xml: &str, tag: &str) -> Option<String> {
+    let start = find_start_tag(xml, tag)?;
+    let content = &xml[start..];
+    let end = content.find("</")?;
+    Some(unescape(&content[..end]))
+}
+
+/// an element found by [elements]
+#[derive(Debug, Clone, Copy)]
+pub struct Element<'a> {
+    attributes: &'a str,
+    /// the raw content between the opening and the closing tag
+    pub content: &'a str,
+}
+
+impl Element<'_> {
+    /// the unescaped value of an attribute
+    pub fn attribute(&self, name: &str) -> Option<String> {
+        let pattern = format!(" {name}=\"");
+        let start = self.attributes.find(&pattern)? + pattern.len();
+        let len = self.attributes[start..].find('"')?;
+        Some(unescape(&self.attributes[start..start + len]))
+    }
+}
+
+/// elements - every element with the given (local) name
+///
+/// elements nested in an element of the same name are not supported
+pub fn elements<'a>(xml: &'a str, tag: &str) -> Vec<Element<'a>> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+    while let Some(pos) = xml[offset..].find('<') {
+        let start = offset + pos + 1;
+        let Some(end) = xml[start..].find('>').map(|len| start + len) else {
+            break;
+        };
+        offset = end + 1;
+        let inner = &xml[start..end];
+        let name = inner.split_whitespace().next().unwrap_or_default().trim_end_matches('/');
+        if name.rsplit(':').next() != Some(tag) {
+            continue;
+        }
+        let content = match inner.ends_with('/') {
+            true => "",
+            false => {
+                let close = format!("</{name}>");
+                xml[offset..].find(&close).map_or("", |len| &xml[offset..offset + len])
+            }
+        };
+        found.push(Element {
+            attributes: inner[name.len()..].trim_end_matches('/'),
+            content,
+        });
+    }
+    found
+}
+
+/// find the end of the opening tag `<tag>` or `<prefix:tag ...>`
+fn find_start_tag(xml: &str, tag: &str) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(pos) = xml[offset..].find('<') {
+        let start = offset + pos + 1;
+        let end = start + xml[start..].find('>')?;
+        let name = xml[start..end].split_whitespace().next().unwrap_or_default();
+        let local = name.rsplit(':').next().unwrap_or_default();
+        if local == tag && !xml[start..end].ends_with('/') {
+            return Some(end + 1);
+        }
+        offset = end;
+    }
+    None
+}
+
+/// escape a string for use in xml text or attributes
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// unescape xml text
+pub fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = concat!(
+        r#"<Group ID="1" Coordinator="RINCON_A">"#,
+        r#"<Member UUID="RINCON_A" Name="Kids &amp; &quot;Play&quot; &lt;2&gt;"><Satellite UUID="RINCON_S" Invisible="1"/></Member>"#,
+        r#"<Member UUID="RINCON_B" Name="Kitchen"/>"#,
+        r#"<Member UUID="RINCON_C" Name="Bedroom" ></Member>"#,
+        r#"</Group>"#,
+        r#"<Group ID="2"/>"#
+    );
+
+    #[test]
+    fn elements_with_and_without_closing_tag() {
+        let groups = elements(XML, "Group");
+        assert_eq!(groups.len(), 2);
+        assert!(groups[0].content.starts_with("<Member UUID=\"RINCON_A\""));
+        assert!(groups[0].content.ends_with("</Member>"));
+        assert_eq!(groups[1].content, "");
+
+        let members = elements(groups[0].content, "Member");
+        let uuids: Vec<String> = members.iter().filter_map(|m| m.attribute("UUID")).collect();
+        assert_eq!(uuids, ["RINCON_A", "RINCON_B", "RINCON_C"]);
+        assert_eq!(members[0].content, r#"<Satellite UUID="RINCON_S" Invisible="1"/>"#);
+        assert_eq!(members[1].content, "");
+        assert_eq!(members[2].content, "");
+        assert_eq!(elements(members[0].content, "Satellite")[0].attribute("Invisible").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn elements_by_local_name() {
+        let xml = r#"<s:Body><u:Volume channel="Master" val="30"/><Volume channel="LF" val="100"/></s:Body>"#;
+        let volumes: Vec<String> = elements(xml, "Volume").iter().filter_map(|v| v.attribute("val")).collect();
+        assert_eq!(volumes, ["30", "100"]);
+        assert!(elements(xml, "Mute").is_empty());
+    }
+
+    #[test]
+    fn attributes() {
+        let groups = elements(XML, "Group");
+        assert_eq!(groups[0].attribute("ID").as_deref(), Some("1"));
+        assert_eq!(groups[0].attribute("Coordinator").as_deref(), Some("RINCON_A"));
+        assert_eq!(groups[1].attribute("ID").as_deref(), Some("2"));
+        assert_eq!(groups[1].attribute("Coordinator"), None);
+
+        let members = elements(XML, "Member");
+        // `ID` doesn't match the end of `UUID`
+        assert_eq!(members[0].attribute("ID"), None);
+        assert_eq!(members[0].attribute("Name").as_deref(), Some("Kids & \"Play\" <2>"));
+        assert_eq!(members[1].attribute("Name").as_deref(), Some("Kitchen"));
+        assert_eq!(members[2].attribute("Name").as_deref(), Some("Bedroom"));
+    }
+
+    #[test]
+    fn values() {
+        let xml = "<s:Body><u:GetVolumeResponse><CurrentVolume>42</CurrentVolume>\
+                   <Uri/><Meta>&lt;DIDL&gt;&amp;amp;</Meta></u:GetVolumeResponse></s:Body>";
+        assert_eq!(value(xml, "CurrentVolume").as_deref(), Some("42"));
+        assert_eq!(value(xml, "Meta").as_deref(), Some("<DIDL>&amp;"));
+        assert_eq!(value(xml, "Missing"), None);
+    }
+
+    #[test]
+    fn escape_roundtrip() {
+        let text = r#"Kids & "Play" <'2'>"#;
+        assert_eq!(escape(text), "Kids &amp; &quot;Play&quot; &lt;&apos;2&apos;&gt;");
+        assert_eq!(unescape(&escape(text)), text);
+        assert_eq!(unescape("&amp;lt;"), "&lt;");
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+};
+
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::Serialize;
+
+use super::{
+    soap::{self, Service},
+    Renderer, RendererError, AV_TRANSPORT, SONOS_PORT,
+};
+
+pub const ZONE_GROUP_TOPOLOGY: Service = Service {
+    urn: "urn:schemas-upnp-org:service:ZoneGroupTopology:1",
+    control_path: "/ZoneGroupTopology/Control",
+    event_path: "/ZoneGroupTopology/Event",
+};
+
+/// the configured renderer a group coordinator streams for, by the coordinator's address
+static COORDINATORS: Lazy<RwLock<HashMap<IpAddr, IpAddr>>> = Lazy::new(Default::default);
+
+/// a group of Sonos speakers playing the same audio
+#[derive(Debug, Clone, Serialize)]
+pub struct ZoneGroup {
+    pub id: String,
+    /// uuid of the speaker that streams for the group
+    pub coordinator: String,
+    pub members: Vec<ZoneMember>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ZoneMember {
+    pub uuid: String,
+    pub name: String,
+    pub ip: Option<IpAddr>,
+}
+
+impl ZoneGroup {
+    pub fn coordinator(&self) -> Option<&ZoneMember> {
+        self.members.iter().find(|member| member.uuid == self.coordinator)
+    }
+
+    pub fn member(&self, ip: IpAddr) -> Option<&ZoneMember> {
+        self.members.iter().find(|member| member.ip == Some(ip))
+    }
+}
+
+/// zone_groups - the current groups of the Sonos household, asked from any speaker
+pub fn zone_groups(addr: SocketAddr) -> Result<Vec<ZoneGroup>, RendererError> {
+    let response = soap::call(addr, &ZONE_GROUP_TOPOLOGY, "GetZoneGroupState", &[])?;
+    let state = soap::value(&response, "ZoneGroupState").ok_or(RendererError::InvalidResponse)?;
+    Ok(parse_zone_groups(&state))
+}
+
+/// the groups in a `ZoneGroupState`
+fn parse_zone_groups(state: &str) -> Vec<ZoneGroup> {
+    soap::elements(state, "ZoneGroup")
+        .into_iter()
+        .map(|group| ZoneGroup {
+            id: group.attribute("ID").unwrap_or_default(),
+            coordinator: group.attribute("Coordinator").unwrap_or_default(),
+            members: soap::elements(group.content, "ZoneGroupMember")
+                .into_iter()
+                // bonded speakers (e.g. surrounds) can't be controlled on their own
+                .filter(|member| member.attribute("Invisible").as_deref() != Some("1"))
+                .map(|member| ZoneMember {
+                    uuid: member.attribute("UUID").unwrap_or_default(),
+                    name: member.attribute("ZoneName").unwrap_or_default(),
+                    ip: member.attribute("Location").as_deref().and_then(location_ip),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// the address in a device description url, e.g. `http://192.168.1.20:1400/xml/...`
+fn location_ip(location: &str) -> Option<IpAddr> {
+    let host = location.split("://").nth(1)?.split('/').next()?;
+    match host.parse::<SocketAddr>() {
+        Ok(addr) => Some(addr.ip()),
+        Err(_) => host.parse().ok(),
+    }
+}
+
+/// join - add a speaker to the group of the coordinator with this uuid
+pub fn join(speaker: IpAddr, coordinator: &str) -> Result<(), RendererError> {
+    soap::call(
+        SocketAddr::new(speaker, SONOS_PORT),
+        &AV_TRANSPORT,
+        "SetAVTransportURI",
+        &[
+            ("InstanceID", "0"),
+            ("CurrentURI", &format!("x-rincon:{coordinator}")),
+            ("CurrentURIMetaData", ""),
+        ],
+    )
+    .map(|_| ())
+}
+
+/// leave - take a speaker out of its group
+pub fn leave(speaker: IpAddr) -> Result<(), RendererError> {
+    soap::call(
+        SocketAddr::new(speaker, SONOS_PORT),
+        &AV_TRANSPORT,
+        "BecomeCoordinatorOfStandaloneGroup",
+        &[("InstanceID", "0")],
+    )
+    .map(|_| ())
+}
+
+/// target - the speaker sonar controls and streams to for a renderer
+///
+/// with `group_coordinator` this is the coordinator of the renderer's group,
+/// which streams for all of its members
+pub fn target(renderer: &Renderer) -> Result<Renderer, RendererError> {
+    if !renderer.group_coordinator {
+        return Ok(renderer.clone());
+    }
+    let ip = renderer.addr.ip();
+    let groups = zone_groups(renderer.addr)?;
+    let coordinator = groups
+        .iter()
+        .find(|group| group.member(ip).is_some())
+        .and_then(ZoneGroup::coordinator)
+        .and_then(|coordinator| coordinator.ip)
+        .ok_or(RendererError::UnknownSpeaker(ip))?;
+
+    let mut coordinators = COORDINATORS.write();
+    coordinators.retain(|_, renderer| *renderer != ip);
+    if coordinator != ip {
+        coordinators.insert(coordinator, ip);
+    }
+    Ok(Renderer {
+        addr: SocketAddr::new(coordinator, SONOS_PORT),
+        ..renderer.clone()
+    })
+}
+
+/// join_members - group the renderer's `group_members` with the speaker sonar streams to
+///
+/// called after the speaker was pointed to sonar's stream, so it coordinates
+/// its own group. members that can't be joined are skipped
+pub fn join_members(renderer: &Renderer, target: &Renderer) -> Result<(), RendererError> {
+    if renderer.group_members.is_empty() {
+        return Ok(());
+    }
+
+    let groups = zone_groups(target.addr)?;
+    let ip = target.addr.ip();
+    let (group, speaker) = groups
+        .iter()
+        .find_map(|group| group.member(ip).map(|speaker| (group, speaker)))
+        .ok_or(RendererError::UnknownSpeaker(ip))?;
+    for &member in renderer.group_members.iter().filter(|&&member| group.member(member).is_none()) {
+        info!("adding '{member}' to the group of renderer '{}'", renderer.name);
+        if let Err(e) = join(member, &speaker.uuid) {
+            warn!("could not add '{member}' to the group of renderer '{}': {e}", renderer.name);
+        }
+    }
+    Ok(())
+}
+
+/// renderer_ip - the configured renderer a client streams for
+///
+/// a group coordinator connects with its own address, which is mapped back
+/// to the renderer, so the renderer's settings apply to the group's stream
+pub fn renderer_ip(client: IpAddr) -> IpAddr {
+    COORDINATORS.read().get(&client).copied().unwrap_or(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a GetZoneGroupState response of a household with a home theater
+    /// (two surrounds and a bonded sub) grouped with the kitchen, and a bedroom
+    const ZONE_GROUP_STATE: &str = concat!(
+        r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+        r#"<s:Body>"#,
+        r#"<u:GetZoneGroupStateResponse xmlns:u="urn:schemas-upnp-org:service:ZoneGroupTopology:1">"#,
+        r#"<ZoneGroupState>&lt;ZoneGroupState&gt;"#,
+        r#"&lt;ZoneGroups&gt;"#,
+        r#"&lt;ZoneGroup Coordinator=&quot;RINCON_000E58A0000101400&quot; ID=&quot;RINCON_000E58A0000101400:2841&quot;&gt;"#,
+        r#"&lt;ZoneGroupMember UUID=&quot;RINCON_000E58A0000101400&quot; Location=&quot;http://192.168.1.20:1400/xml/device_description.xml&quot; ZoneName=&quot;Living Room&quot; Icon=&quot;&quot; Configuration=&quot;1&quot; SoftwareVersion=&quot;79.1-56030&quot; HTSatChanMapSet=&quot;RINCON_000E58A0000101400:LF,RF;RINCON_000E58A0000301400:LR;RINCON_000E58A0000401400:RR&quot;&gt;"#,
+        r#"&lt;Satellite UUID=&quot;RINCON_000E58A0000301400&quot; Location=&quot;http://192.168.1.30:1400/xml/device_description.xml&quot; ZoneName=&quot;Living Room&quot; Invisible=&quot;1&quot; HTSatChanMapSet=&quot;RINCON_000E58A0000101400:LF,RF;RINCON_000E58A0000301400:LR&quot;/&gt;"#,
+        r#"&lt;Satellite UUID=&quot;RINCON_000E58A0000401400&quot; Location=&quot;http://192.168.1.31:1400/xml/device_description.xml&quot; ZoneName=&quot;Living Room&quot; Invisible=&quot;1&quot; HTSatChanMapSet=&quot;RINCON_000E58A0000101400:LF,RF;RINCON_000E58A0000401400:RR&quot;/&gt;"#,
+        r#"&lt;/ZoneGroupMember&gt;"#,
+        r#"&lt;ZoneGroupMember UUID=&quot;RINCON_000E58A0000501400&quot; Location=&quot;http://192.168.1.32:1400/xml/device_description.xml&quot; ZoneName=&quot;Living Room&quot; Invisible=&quot;1&quot; Configuration=&quot;1&quot;/&gt;"#,
+        r#"&lt;ZoneGroupMember UUID=&quot;RINCON_000E58A0000201400&quot; Location=&quot;http://192.168.1.22:1400/xml/device_description.xml&quot; ZoneName=&quot;Kids &amp;amp; &amp;quot;Kitchen&amp;quot;&quot; Configuration=&quot;1&quot;/&gt;"#,
+        r#"&lt;/ZoneGroup&gt;"#,
+        r#"&lt;ZoneGroup Coordinator=&quot;RINCON_000E58A0000601400&quot; ID=&quot;RINCON_000E58A0000601400:97&quot;&gt;"#,
+        r#"&lt;ZoneGroupMember UUID=&quot;RINCON_000E58A0000601400&quot; Location=&quot;http://192.168.1.23:1400/xml/device_description.xml&quot; ZoneName=&quot;Bedroom&quot; Configuration=&quot;1&quot;&gt;"#,
+        r#"&lt;/ZoneGroupMember&gt;"#,
+        r#"&lt;/ZoneGroup&gt;"#,
+        r#"&lt;/ZoneGroups&gt;"#,
+        r#"&lt;VanishedDevices&gt;"#,
+        r#"&lt;/VanishedDevices&gt;"#,
+        r#"&lt;/ZoneGroupState&gt;</ZoneGroupState>"#,
+        r#"</u:GetZoneGroupStateResponse>"#,
+        r#"</s:Body>"#,
+        r#"</s:Envelope>"#,
+    );
+
+    fn groups() -> Vec<ZoneGroup> {
+        parse_zone_groups(&soap::value(ZONE_GROUP_STATE, "ZoneGroupState").unwrap())
+    }
+
+    #[test]
+    fn zone_groups_of_household() {
+        let groups = groups();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].id, "RINCON_000E58A0000101400:2841");
+        assert_eq!(groups[0].coordinator, "RINCON_000E58A0000101400");
+        assert_eq!(groups[0].coordinator().unwrap().name, "Living Room");
+        assert_eq!(groups[1].coordinator().unwrap().ip, Some("192.168.1.23".parse().unwrap()));
+    }
+
+    #[test]
+    fn zone_groups_skip_bonded_speakers() {
+        let groups = groups();
+        // the surrounds are satellites of the living room, the sub is invisible
+        let uuids: Vec<&str> = groups[0].members.iter().map(|member| member.uuid.as_str()).collect();
+        assert_eq!(uuids, ["RINCON_000E58A0000101400", "RINCON_000E58A0000201400"]);
+        for ip in ["192.168.1.30", "192.168.1.31", "192.168.1.32"] {
+            assert!(groups.iter().all(|group| group.member(ip.parse().unwrap()).is_none()));
+        }
+        // a member with a closing tag and no satellites
+        assert_eq!(groups[1].members.len(), 1);
+        assert_eq!(groups[1].members[0].name, "Bedroom");
+    }
+
+    #[test]
+    fn zone_groups_unescape_names() {
+        let kitchen = groups()[0].member("192.168.1.22".parse().unwrap()).cloned().unwrap();
+        assert_eq!(kitchen.name, "Kids & \"Kitchen\"");
+    }
+
+    #[test]
+    fn location_ips() {
+        let ip = |location| location_ip(location).map(|ip| ip.to_string());
+        assert_eq!(ip("http://192.168.1.20:1400/xml/device_description.xml").as_deref(), Some("192.168.1.20"));
+        assert_eq!(ip("http://192.168.1.20/xml/device_description.xml").as_deref(), Some("192.168.1.20"));
+        assert_eq!(ip("http://[fe80::1]:1400/xml/device_description.xml").as_deref(), Some("fe80::1"));
+        assert_eq!(ip("http://sonos.local:1400/xml/device_description.xml"), None);
+        assert_eq!(ip("192.168.1.20"), None);
+    }
+}
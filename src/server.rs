@@ -1,98 +1,375 @@
 use std::{
-    io::{BufRead, BufReader, Write},
-    net::{TcpListener, TcpStream}, error::Error,
+    io::{self, BufReader, BufWriter, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream}, error::Error,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    thread,
+    time::{Duration, Instant},
 };
 
-use crossbeam_channel::{Receiver, bounded};
-use dasp_sample::Sample;
-use log::{debug, info};
+use log::{debug, error, info, warn};
+use parking_lot::Mutex;
 
-use crate::{audio::format::wav::create_header, CLIENTS, CONFIG};
+use crate::{
+    api,
+    audio::format::{lpcm, wav, StreamingFormat},
+    dsp::Pipeline,
+    http::{write_chunk, Request},
+    renderer::{
+        events::{self, EVENT_PATH},
+        sonos,
+    },
+    stream::{
+        buffer::{BufferRead, JitterBuffer},
+        preroll::Preroll,
+        renderer_clock::RendererClock,
+        sync::mean_position,
+        ClientStats,
+    },
+    CLIENTS, CONFIG, RING, SYNC_GROUP,
+};
+
+/// the http response header of the audio stream
+fn headers(content_type: &str) -> String {
+    format!(
+        concat!(
+            "HTTP/1.1 200 OK\r\n",
+            "Connection: close\r\n",
+            "Content-Type: {content_type}\r\n",
+            "Transfer-Encoding: chunked\r\n",
+            "\r\n"
+        ),
+        content_type = content_type
+    )
+}
 
-const HEADERS: &str = concat!(
-    "HTTP/1.1 200 OK\r\n",
-    "Connection: close\r\n",
-    "Content-Type: audio/vnd.wave;codec=1\r\n",
-    "Transfer-Encoding: chunked\r\n",
-    "\r\n"
-);
+/// set when the listener has to move to the configured address
+static REBIND: AtomicBool = AtomicBool::new(false);
+/// the address the listener is bound to
+static LISTENER_ADDR: Mutex<Option<SocketAddr>> = Mutex::new(None);
+
+fn server_addr() -> SocketAddr {
+    let config = CONFIG.read();
+    SocketAddr::new(config.server.network, config.server.port)
+}
 
 pub fn start_server() {
-    let addr = {
-        let config = CONFIG.read();
-        format!("{}:{}", config.server.network, config.server.port)
-    };
+    let addr = server_addr();
     info!("starting server on '{addr}'");
 
-    let listener = TcpListener::bind(addr).unwrap();
-    
-    for incoming in listener.incoming() {
-        std::thread::spawn(|| handle_client(incoming.unwrap()));
+    let mut listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("could not start server on '{addr}': {e}");
+            std::process::exit(1);
+        }
+    };
+    *LISTENER_ADDR.lock() = Some(addr);
+
+    loop {
+        for incoming in listener.incoming() {
+            match incoming {
+                Ok(stream) => {
+                    // this may be a client instead of the connection of `rebind`,
+                    // the wake-up connection closes without a request
+                    std::thread::spawn(|| handle_client(stream));
+                }
+                Err(e) => warn!("could not accept connection: {e}"),
+            }
+            if REBIND.swap(false, Ordering::SeqCst) {
+                break;
+            }
+        }
+        listener = move_listener(listener);
+    }
+}
+
+/// bind the listener to the configured address, keeps the old one on errors
+fn move_listener(listener: TcpListener) -> TcpListener {
+    let (old, new) = (listener.local_addr(), server_addr());
+    // free the port first, the new address may overlap the old one
+    drop(listener);
+    match TcpListener::bind(new) {
+        Ok(listener) => {
+            info!("server moved to '{new}'");
+            *LISTENER_ADDR.lock() = Some(new);
+            listener
+        }
+        Err(e) => {
+            error!("could not move server to '{new}': {e}");
+            let old = old.ok().or(*LISTENER_ADDR.lock()).unwrap_or(new);
+            TcpListener::bind(old).unwrap_or_else(|e| {
+                error!("could not restart server on '{old}': {e}");
+                std::process::exit(1);
+            })
+        }
+    }
+}
+
+/// move the listener to the configured address
+///
+/// clients that are already streaming keep their connection
+pub fn rebind() {
+    let Some(addr) = *LISTENER_ADDR.lock() else {
+        return;
+    };
+    REBIND.store(true, Ordering::SeqCst);
+
+    // wake up the blocking accept with a connection to ourselves
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    if let Err(e) = TcpStream::connect_timeout(&SocketAddr::new(ip, addr.port()), Duration::from_secs(1)) {
+        warn!("could not wake up the server to move it: {e}");
     }
 }
 
 fn handle_client(mut stream: TcpStream) {
     let ip = stream.peer_addr().unwrap().ip();
-    info!("client '{}' connected", ip);
 
-    let buf_reader = BufReader::new(&mut stream);
-    let http_request: Vec<_> = buf_reader
-        .lines()
-        .map(|result| result.unwrap())
-        .take_while(|line| !line.is_empty())
-        .collect();
+    let mut buf_reader = BufReader::new(&mut stream);
+    let request = match Request::read(&mut buf_reader) {
+        Ok(request) => request,
+        Err(e) => {
+            debug!("invalid request from '{ip}': {e}");
+            return;
+        }
+    };
     
     debug!(
         "Request ({}): {:#?}",
         stream.peer_addr().unwrap(),
-        http_request
+        request
     );
 
-    // http response header
-    stream.write_all(HEADERS.as_bytes()).unwrap();
+    if request.method == "NOTIFY" && request.path.starts_with(EVENT_PATH) {
+        if let Err(e) = events::handle_notify(&stream, &request) {
+            debug!("event from '{ip}' failed: {e}");
+        }
+        return;
+    }
 
-    let (s, r) = bounded(1);
-    CLIENTS.write().insert(ip, s);
+    if request.path.starts_with("/api/") {
+        if let Err(e) = api::handle_request(&stream, &request) {
+            debug!("api request from '{ip}' failed: {e}");
+        }
+        return;
+    }
+
+    // a sonos group coordinator streams for the configured renderer
+    let ip = sonos::renderer_ip(ip);
+    info!("client '{}' connected", ip);
+
+    // send every chunk right away instead of waiting for more data
+    if let Err(e) = stream.set_nodelay(true) {
+        warn!("could not set TCP_NODELAY for '{ip}': {e}");
+    }
 
-    match send_audio_stream(&stream, r) {
+    let stats = Arc::new(ClientStats::default());
+    CLIENTS.write().insert(ip, stats.clone());
+
+    match send_audio_stream(&stream, ip, &stats) {
         Ok(()) => {}, // this function does not return OK because of the endless loop
         Err(_) => {   // it only returns ERR when the client disconnected
             CLIENTS.write().remove(&ip);
-            info!("client '{}' disconnected", ip);
+            info!("client '{}' disconnected ({:?})", ip, stats.snapshot());
         }
     }
 }
 
 /// returns Err when the tcp stream is closed and the data cannot be flushed anymore
-fn send_audio_stream(stream: &TcpStream, receiver: Receiver<Vec<f32>>) -> Result<(), Box<dyn Error>> {
-    let bits_per_sample = CONFIG.read().audio.bits_per_sample;
+///
+/// the samples are read from the client's jitter buffer and sent in fixed
+/// size chunks at the rate they are played back. all buffers are allocated
+/// once per client, the streaming loop itself does not allocate
+fn send_audio_stream(stream: &TcpStream, ip: IpAddr, stats: &ClientStats) -> Result<(), Box<dyn Error>> {
+    let (format, bits_per_sample, profile, capture_timeout, preroll, group, start_timeout) = {
+        let config = CONFIG.read();
+        let preroll = config
+            .renderer_for(ip)
+            .map(|renderer| (renderer.preroll, renderer.preroll_ms))
+            .unwrap_or_default();
+        let (format, bits_per_sample) = config.stream_format(ip);
+        debug!("streaming {format} with {bits_per_sample} bits per sample to '{ip}'");
+        (
+            format,
+            bits_per_sample,
+            config.app.latency_profile(),
+            Duration::from_millis(config.app.capture_timeout as u64),
+            preroll,
+            config.sync_group(),
+            Duration::from_millis(config.multiroom.start_timeout_ms as u64),
+        )
+    };
+    let sample_rate = RING.sample_rate();
+    let chunk_frames = sample_rate as usize * profile.chunk_ms / 1000;
+    let period = Duration::from_secs_f64(chunk_frames as f64 / sample_rate as f64);
+
+    let mut samples = vec![0f32; chunk_frames * RING.channels() as usize];
+    let mut sender = ChunkSender {
+        writer: BufWriter::with_capacity(samples.len() * 4 + 16, stream),
+        bytes: Vec::with_capacity(samples.len() * 4),
+        format,
+        bits_per_sample,
+        stats,
+        pipeline: Pipeline::new(ip, sample_rate, RING.channels() as usize),
+    };
+
+    // http response header
+    let content_type = match format {
+        StreamingFormat::Wav => "audio/vnd.wave;codec=1".to_string(),
+        format => format.mime_type(sample_rate, RING.channels(), bits_per_sample),
+    };
+    sender.writer.write_all(headers(&content_type).as_bytes())?;
+    // send wav header with an "infinite size", lpcm has no header
+    if format == StreamingFormat::Wav {
+        write_chunk(&mut sender.writer, &wav::create_header(sample_rate, bits_per_sample))?;
+    }
 
-    // send wav header with an "infinite size"
-    send_encoded(stream, &create_header(48000, bits_per_sample))?;
+    let mut buffer = JitterBuffer::new(&RING, &profile, samples.len(), stats);
+    // multiroom members start at the same position, or where the others are
+    // when they connect later, and refill to the full target after underruns,
+    // so they all keep the same distance to the capture
+    let _member = if group.contains(&ip) {
+        buffer.prefill_to_target();
+        debug!("'{ip}' waiting for the other renderers of the multiroom group");
+        let current = || {
+            mean_position(&group, &CLIENTS.read()).map(|position| position.round() as u64)
+        };
+        let start = SYNC_GROUP.join(ip, &group, &RING, buffer.target(), start_timeout, current);
+        buffer.seek(&RING, start);
+        Some(GroupMember)
+    } else {
+        None
+    };
+    let mut pacer = Pacer::new(period, Duration::from_millis(profile.buffer_ms as u64));
+    let queue = (sample_rate as usize * profile.queue_ms / 1000) as u64;
+    let bytes_per_frame = RING.channels() as usize * bits_per_sample as usize / 8;
+    let mut clock = RendererClock::new(queue, bytes_per_frame);
 
-    let mut buffer = Vec::with_capacity(16384);
+    // send the pre-roll burst right away to fill the renderer's buffer
+    let (preroll_mode, preroll_ms) = preroll;
+    let mut preroll = Preroll::new(&RING, preroll_mode, preroll_ms, buffer.position(), capture_timeout);
     loop {
-        // wait for samples from the audio capture thread
-        let samples = receiver.recv()?;
+        let n = preroll.read(&mut samples);
+        if n == 0 {
+            break;
+        }
+        sender.send(&mut samples[..n])?;
+    }
 
-        // convert f32 samples to i16 samples as bytes
-        for sample in samples {
-            let sample = i16::from_sample(sample);
-            buffer.extend_from_slice(&sample.to_le_bytes());
+    loop {
+        // (re)fill the buffer before streaming starts or after an underrun.
+        // if the capture went quiet, keep the stream alive with silence
+        pacer.reset();
+        let mut synthetic = false;
+        while !buffer.prefill(period) {
+            if !synthetic && RING.since_last_write() >= capture_timeout {
+                debug!("no audio captured for {capture_timeout:?}, sending silence");
+                synthetic = true;
+            }
+            if synthetic {
+                samples.fill(0.0);
+                sender.send(&mut samples)?;
+                stats.silence_samples.fetch_add(samples.len() as u64, Ordering::Relaxed);
+                pacer.wait();
+            }
         }
+        debug!("client buffer filled ({} samples)", buffer.fill());
+        clock.reset();
 
-        // send buffer to client
-        send_encoded(stream, &buffer)?;
+        loop {
+            match buffer.read(&mut samples) {
+                BufferRead::Samples(n) => sender.send(&mut samples[..n])?,
+                BufferRead::Underrun => {
+                    warn!("client buffer underrun, refilling");
+                    break;
+                }
+            }
+            // send at the pace the renderer plays
+            pacer.set_ratio(clock.update(stream));
+            stats
+                .renderer_drift_ppm
+                .store(clock.drift_ppm().round() as i64, Ordering::Relaxed);
+            pacer.wait();
+        }
+    }
+}
 
-        // clear the buffer for the next samples
-        buffer.clear();
+/// leaves the multiroom group when the stream ends
+struct GroupMember;
+
+impl Drop for GroupMember {
+    fn drop(&mut self) {
+        SYNC_GROUP.leave();
     }
 }
 
-/// encode data and write it the the TCP stream
-fn send_encoded(mut stream: &TcpStream, data: &[u8]) -> std::io::Result<()> {
-    stream.write_all(format!("{:x}\r\n", data.len()).as_bytes())?;
-    stream.write_all(data)?;
-    stream.write_all(b"\r\n")?;
-    stream.flush()
+/// processes and encodes samples and writes them as http chunks to a client
+struct ChunkSender<'a> {
+    writer: BufWriter<&'a TcpStream>,
+    bytes: Vec<u8>,
+    format: StreamingFormat,
+    bits_per_sample: u16,
+    stats: &'a ClientStats,
+    pipeline: Pipeline,
+}
+
+impl ChunkSender<'_> {
+    fn send(&mut self, samples: &mut [f32]) -> io::Result<()> {
+        let limited = self.pipeline.process(samples);
+        if limited > 0 {
+            self.stats.limiter_events.fetch_add(limited, Ordering::Relaxed);
+        }
+        // convert f32 samples to pcm bytes and send them to the client
+        match self.format {
+            StreamingFormat::Lpcm => lpcm::encode_samples(samples, self.bits_per_sample, &mut self.bytes),
+            _ => wav::encode_samples(samples, self.bits_per_sample, &mut self.bytes),
+        }
+        write_chunk(&mut self.writer, &self.bytes)?;
+        self.stats
+            .sent_samples
+            .fetch_add(samples.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// sends chunks at the rate they are played back
+struct Pacer {
+    next: Instant,
+    period: Duration,
+    /// scales the period to the renderer's clock
+    ratio: f64,
+    max_lateness: Duration,
+}
+
+impl Pacer {
+    fn new(period: Duration, max_lateness: Duration) -> Self {
+        Self {
+            next: Instant::now(),
+            period,
+            ratio: 1.0,
+            max_lateness,
+        }
+    }
+
+    fn set_ratio(&mut self, ratio: f64) {
+        self.ratio = ratio;
+    }
+
+    fn reset(&mut self) {
+        self.next = Instant::now();
+    }
+
+    /// wait until the next chunk is due, but don't try to catch up after long stalls
+    fn wait(&mut self) {
+        self.next += self.period.mul_f64(self.ratio);
+        let now = Instant::now();
+        if self.next > now {
+            thread::sleep(self.next - now);
+        } else if now - self.next > self.max_lateness {
+            self.next = now;
+        }
+    }
 }
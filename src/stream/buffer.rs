@@ -0,0 +1,156 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
+use crate::audio::ring::{ReadResult, RingReader, SampleRing};
+
+use super::{
+    drift::{DriftEstimator, Resampler, MAX_CORRECTION},
+    latency::LatencyProfile,
+    ClientStats,
+};
+
+/// headroom above the target fill level before old samples are dropped
+const MIN_HEADROOM_MS: usize = 100;
+
+/// the outcome of a [JitterBuffer::read] call
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BufferRead {
+    /// `n` samples were copied into the output buffer
+    Samples(usize),
+    /// not enough samples are buffered, the buffer has to be refilled
+    Underrun,
+}
+
+/// JitterBuffer - the per-client audio buffer
+///
+/// Keeps `target` samples between the capture callback and the client's
+/// streaming thread. This absorbs bursty capture callbacks and short
+/// network hiccups. The buffer is filled up to the target before streaming
+/// (re)starts; if it grows far beyond the target the oldest samples are
+/// dropped to keep the latency bounded.
+///
+/// The buffer is drained at the pace of the renderer's clock, the fill level
+/// is used to estimate the clock drift between the capture device and the
+/// renderer, which is compensated by slightly resampling the audio.
+pub struct JitterBuffer<'a> {
+    reader: RingReader<'a>,
+    target: u64,
+    prefill: u64,
+    limit: u64,
+    stats: &'a ClientStats,
+    channels: usize,
+    drift: DriftEstimator,
+    resampler: Resampler,
+    scratch: Vec<f32>,
+}
+
+impl<'a> JitterBuffer<'a> {
+    /// Creates a buffer sized for the latency profile, starting at the current end of the ring.
+    ///
+    /// `chunk_size` is the largest number of samples read at once.
+    pub fn new(
+        ring: &'a SampleRing,
+        profile: &LatencyProfile,
+        chunk_size: usize,
+        stats: &'a ClientStats,
+    ) -> Self {
+        let target = ring.ms_to_samples(profile.buffer_ms).min(ring.max_lag() / 2);
+        let channels = ring.channels() as usize;
+        // one chunk has to be buffered before anything can be sent, plus the
+        // frames the resampler reads ahead at the largest correction
+        let read_ahead = ((chunk_size / channels) as f64 * MAX_CORRECTION).ceil() as usize + 2;
+        let min_prefill = (chunk_size + read_ahead * channels) as u64;
+        let prefill = ring.ms_to_samples(profile.prefill_ms).min(target).max(min_prefill);
+        let headroom = target.max(ring.ms_to_samples(MIN_HEADROOM_MS));
+        // the resampler may need a few frames more than it produces
+        let scratch_size = chunk_size * 2 + channels * 4;
+        Self {
+            reader: ring.reader(),
+            target,
+            prefill,
+            limit: (target + headroom).min(ring.max_lag()),
+            stats,
+            channels,
+            drift: DriftEstimator::new(target),
+            resampler: Resampler::new(channels, scratch_size),
+            scratch: vec![0f32; scratch_size],
+        }
+    }
+
+    /// the fill level the buffer aims for, in samples
+    pub fn target(&self) -> u64 {
+        self.target
+    }
+
+    /// ring position of the next sample read from the buffer
+    pub fn position(&self) -> u64 {
+        self.reader.position()
+    }
+
+    /// Moves the buffer to a ring position, e.g. the common start of a multiroom group.
+    pub fn seek(&mut self, ring: &'a SampleRing, pos: u64) {
+        self.reader = ring.reader_before(pos, 0);
+        self.resampler.reset();
+        self.drift.reset();
+    }
+
+    /// Refill to the full target after underruns instead of the prefill level.
+    ///
+    /// Multiroom members do this to come back in line with the group.
+    pub fn prefill_to_target(&mut self) {
+        self.prefill = self.target;
+    }
+
+    /// number of samples currently buffered
+    pub fn fill(&self) -> u64 {
+        self.reader.available()
+    }
+
+    /// Waits until enough samples are buffered to start streaming.
+    ///
+    /// Returns `false` if not enough samples arrived before the timeout.
+    pub fn prefill(&self, timeout: Duration) -> bool {
+        self.reader.wait(self.prefill, timeout)
+    }
+
+    /// Fills `out` with exactly `out.len()` drift compensated samples.
+    ///
+    /// Returns [BufferRead::Underrun] (and copies nothing) if not enough samples are buffered.
+    pub fn read(&mut self, out: &mut [f32]) -> BufferRead {
+        let mut fill = self.fill();
+        if fill > self.limit {
+            let dropped = fill - self.target;
+            self.reader.skip(dropped);
+            self.stats.overruns.fetch_add(1, Ordering::Relaxed);
+            self.stats.dropped_samples.fetch_add(dropped, Ordering::Relaxed);
+            fill = self.target;
+        }
+
+        let ratio = self.drift.update(fill);
+        let needed = self.resampler.input_needed(out.len() / self.channels, ratio);
+        if fill < needed as u64 {
+            self.stats.underruns.fetch_add(1, Ordering::Relaxed);
+            self.drift.reset();
+            return BufferRead::Underrun;
+        }
+
+        match self.reader.read(&mut self.scratch[..needed]) {
+            ReadResult::Samples(n) => {
+                self.resampler.push(&self.scratch[..n]);
+                self.resampler.process(ratio, out);
+                self.stats.buffered_samples.store(self.fill(), Ordering::Relaxed);
+                self.stats.position.store(self.position(), Ordering::Relaxed);
+                self.stats
+                    .drift_ppm
+                    .store(self.drift.drift_ppm().round() as i64, Ordering::Relaxed);
+                BufferRead::Samples(out.len())
+            }
+            ReadResult::Overrun(skipped) => {
+                self.stats.overruns.fetch_add(1, Ordering::Relaxed);
+                self.stats.dropped_samples.fetch_add(skipped, Ordering::Relaxed);
+                self.resampler.reset();
+                self.drift.reset();
+                BufferRead::Underrun
+            }
+        }
+    }
+}
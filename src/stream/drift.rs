@@ -0,0 +1,233 @@
+/// how fast the fill level estimate follows the measured fill level (per chunk)
+const SMOOTHING: f64 = 0.02;
+/// proportional gain: ratio correction per relative fill error
+const KP: f64 = 0.002;
+/// integral gain: how fast the long term drift estimate adapts
+const KI: f64 = 0.000_000_5;
+/// the largest clock drift that is compensated (2000 ppm)
+const MAX_DRIFT: f64 = 0.002;
+/// the largest total resampling correction (5000 ppm)
+pub const MAX_CORRECTION: f64 = 0.005;
+
+/// DriftEstimator - estimates the clock drift between the two sides of a
+/// buffer from its fill level
+///
+/// The buffer of a client is filled at the pace of the capture clock and drained
+/// at the pace of the renderer's clock (see [RendererClock](super::renderer_clock::RendererClock)).
+/// If the capture clock runs faster, the buffer slowly fills up; if it runs
+/// slower, the buffer slowly drains. A PI controller turns the deviation from
+/// the target fill level into a resampling ratio: the integral part converges
+/// to the actual drift, the proportional part pulls the fill level back to the target.
+#[derive(Debug, Clone)]
+pub struct DriftEstimator {
+    target: f64,
+    fill: Option<f64>,
+    drift: f64,
+    max_drift: f64,
+    max_correction: f64,
+}
+
+impl DriftEstimator {
+    pub fn new(target: u64) -> Self {
+        Self::with_limits(target, MAX_DRIFT, MAX_CORRECTION)
+    }
+
+    /// Creates an estimator that compensates at most `max_drift`, and corrects
+    /// the ratio by at most `max_correction` in total.
+    pub fn with_limits(target: u64, max_drift: f64, max_correction: f64) -> Self {
+        Self {
+            target: target.max(1) as f64,
+            fill: None,
+            drift: 0.0,
+            max_drift,
+            max_correction,
+        }
+    }
+
+    /// Feeds the current fill level and returns the resampling ratio
+    /// (input samples consumed per output sample).
+    pub fn update(&mut self, fill: u64) -> f64 {
+        let fill = match self.fill {
+            Some(smoothed) => smoothed + SMOOTHING * (fill as f64 - smoothed),
+            None => fill as f64,
+        };
+        self.fill = Some(fill);
+
+        let error = (fill - self.target) / self.target;
+        self.drift = (self.drift + KI * error).clamp(-self.max_drift, self.max_drift);
+        1.0 + (self.drift + KP * error).clamp(-self.max_correction, self.max_correction)
+    }
+
+    /// Forgets the smoothed fill level, e.g. after the buffer was refilled.
+    ///
+    /// The drift estimate is kept, the clocks don't change because of an underrun.
+    pub fn reset(&mut self) {
+        self.fill = None;
+    }
+
+    /// the estimated clock drift in parts per million
+    pub fn drift_ppm(&self) -> f64 {
+        self.drift * 1_000_000.0
+    }
+}
+
+/// Resampler - a streaming linear interpolation resampler for interleaved samples
+///
+/// Used for the fine grained rate corrections of the drift compensation,
+/// where the ratio stays within a few thousandths of 1.0.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    channels: usize,
+    /// pending input frames, the first frame is the one before `pos`
+    pending: Vec<f32>,
+    /// fractional read position within the first two pending frames
+    pos: f64,
+}
+
+impl Resampler {
+    /// Creates a resampler; `capacity` is the largest number of samples pushed at once.
+    pub fn new(channels: usize, capacity: usize) -> Self {
+        Self {
+            channels: channels.max(1),
+            pending: Vec::with_capacity(capacity * 2 + channels * 4),
+            pos: 0.0,
+        }
+    }
+
+    /// Number of input samples that have to be pushed to produce `out_frames` frames.
+    pub fn input_needed(&self, out_frames: usize, ratio: f64) -> usize {
+        if out_frames == 0 {
+            return 0;
+        }
+        let last = self.pos + (out_frames - 1) as f64 * ratio;
+        let frames = last.floor() as usize + 2;
+        frames.saturating_sub(self.pending_frames()) * self.channels
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        self.pending.extend_from_slice(samples);
+    }
+
+    /// Fills `out` with resampled frames.
+    ///
+    /// [Resampler::input_needed] samples must have been pushed before.
+    pub fn process(&mut self, ratio: f64, out: &mut [f32]) {
+        let channels = self.channels;
+        for (k, frame) in out.chunks_exact_mut(channels).enumerate() {
+            let t = self.pos + k as f64 * ratio;
+            let i = t.floor() as usize;
+            let frac = (t - i as f64) as f32;
+            let a = &self.pending[i * channels..(i + 1) * channels];
+            let b = &self.pending[(i + 1) * channels..(i + 2) * channels];
+            for (c, sample) in frame.iter_mut().enumerate() {
+                *sample = a[c] + (b[c] - a[c]) * frac;
+            }
+        }
+
+        // drop the frames that are no longer needed
+        let end = self.pos + (out.len() / channels) as f64 * ratio;
+        let consumed = (end.floor() as usize).min(self.pending_frames());
+        self.pending.drain(..consumed * channels);
+        self.pos = end - consumed as f64;
+    }
+
+    /// Discards all pending input.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.pos = 0.0;
+    }
+
+    fn pending_frames(&self) -> usize {
+        self.pending.len() / self.channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 200ms of stereo at 48kHz, drained in 10ms chunks
+    const TARGET: u64 = 19200;
+    const CHUNK: f64 = 960.0;
+
+    /// runs a buffer filled `drift` faster than it is drained, returns the
+    /// estimator and the lowest and highest fill relative to the target
+    fn run(drift: f64, chunks: usize) -> (DriftEstimator, f64, f64) {
+        let mut estimator = DriftEstimator::new(TARGET);
+        let mut fill = TARGET as f64;
+        let (mut lowest, mut highest) = (fill, fill);
+        for _ in 0..chunks {
+            let ratio = estimator.update(fill as u64);
+            fill += CHUNK * (1.0 + drift) - CHUNK * ratio;
+            lowest = lowest.min(fill);
+            highest = highest.max(fill);
+        }
+        (estimator, lowest / TARGET as f64, highest / TARGET as f64)
+    }
+
+    #[test]
+    fn estimate_converges_to_the_drift() {
+        for ppm in [150.0, -150.0, 1000.0] {
+            let (estimator, lowest, highest) = run(ppm / 1e6, 100_000);
+            let estimate = estimator.drift_ppm();
+            assert!((estimate - ppm).abs() < ppm.abs() * 0.01, "{estimate} ppm for {ppm} ppm");
+            // the proportional part keeps the fill level close to the target meanwhile
+            let bound = 0.25 * ppm.abs() / 1000.0;
+            assert!(lowest > 1.0 - bound && highest < 1.0 + bound, "{lowest}..{highest}");
+        }
+    }
+
+    #[test]
+    fn drift_and_correction_are_limited() {
+        let mut estimator = DriftEstimator::new(TARGET);
+        for _ in 0..100_000 {
+            let ratio = estimator.update(TARGET * 2);
+            assert!(ratio <= 1.0 + MAX_CORRECTION, "{ratio}");
+        }
+        assert_eq!(estimator.drift_ppm(), MAX_DRIFT * 1e6);
+    }
+
+    /// resamples a ramp in chunks of 480 frames, the ratio changes every chunk
+    fn resample_ramp(ratios: &[f64]) -> (Vec<f32>, Vec<f64>) {
+        const FRAMES: usize = 480;
+        let ramp = |frame: usize| [frame as f32 * 1e-3, frame as f32 * -1e-3];
+        let mut resampler = Resampler::new(2, FRAMES * 2);
+        let (mut output, mut expected) = (Vec::new(), Vec::new());
+        let (mut next, mut t) = (0, 0.0);
+        for &ratio in ratios {
+            let needed = resampler.input_needed(FRAMES, ratio) / 2;
+            let input: Vec<f32> = (next..next + needed).flat_map(ramp).collect();
+            next += needed;
+            resampler.push(&input);
+            let mut out = vec![0.0; FRAMES * 2];
+            resampler.process(ratio, &mut out);
+            output.extend(out);
+            for _ in 0..FRAMES {
+                expected.push(t);
+                t += ratio;
+            }
+        }
+        (output, expected)
+    }
+
+    #[test]
+    fn resampler_passes_through_at_ratio_one() {
+        let (output, expected) = resample_ramp(&[1.0; 8]);
+        for (frame, t) in output.chunks(2).zip(expected) {
+            assert_eq!(frame, [t as f32 * 1e-3, t as f32 * -1e-3]);
+        }
+    }
+
+    #[test]
+    fn resampler_reads_at_the_ratio_without_jumps() {
+        let ratios = [1.003, 0.997, 1.0, 1.0005, 0.9995, 1.005, 0.995, 1.002];
+        let (output, expected) = resample_ramp(&ratios);
+        // the ramp is interpolated exactly, so every frame is at its read position,
+        // also across the chunks
+        for (i, (frame, t)) in output.chunks(2).zip(expected).enumerate() {
+            let value = t * 1e-3;
+            assert!((frame[0] as f64 - value).abs() < 1e-5, "frame {i}: {} != {value}", frame[0]);
+            assert!((frame[1] as f64 + value).abs() < 1e-5, "frame {i}: {} != {}", frame[1], -value);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dsp::limiter::LOOKAHEAD;
+
+/// trade-off between delay and robustness of the audio stream
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LatencyMode {
+    /// minimal buffering, e.g. for watching videos
+    Low,
+    #[default]
+    Balanced,
+    /// large buffers for busy machines or flaky networks
+    Safe,
+}
+
+/// the buffer sizes used for a [LatencyMode]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LatencyProfile {
+    pub mode: LatencyMode,
+    /// target fill level of the client buffers
+    pub buffer_ms: usize,
+    /// samples buffered before the first chunk is sent, `0` sends the first chunk
+    /// as soon as it is captured
+    pub prefill_ms: usize,
+    /// duration of the audio sent in a single chunk
+    pub chunk_ms: usize,
+    /// audio kept in the socket's send queue once the renderer's buffer is full,
+    /// the renderer's clock is followed through it
+    pub queue_ms: usize,
+    /// request the smallest capture buffer the device supports, with the floor
+    /// `MIN_BUFFER_FRAMES` of the capture, smaller buffers overrun on most systems
+    pub min_capture_buffer: bool,
+}
+
+impl LatencyMode {
+    pub fn profile(self) -> LatencyProfile {
+        match self {
+            LatencyMode::Low => LatencyProfile {
+                mode: self,
+                buffer_ms: 40,
+                prefill_ms: 0,
+                chunk_ms: 5,
+                queue_ms: 10,
+                min_capture_buffer: true,
+            },
+            LatencyMode::Balanced => LatencyProfile {
+                mode: self,
+                buffer_ms: 200,
+                prefill_ms: 200,
+                chunk_ms: 10,
+                queue_ms: 40,
+                min_capture_buffer: false,
+            },
+            LatencyMode::Safe => LatencyProfile {
+                mode: self,
+                buffer_ms: 500,
+                prefill_ms: 500,
+                chunk_ms: 20,
+                queue_ms: 100,
+                min_capture_buffer: false,
+            },
+        }
+    }
+}
+
+impl LatencyProfile {
+    /// Expected delay between capturing a sample and sending it to a client.
+    ///
+    /// `capture_buffer` is the duration of the capture device's buffer,
+    /// `limiter` adds the look-ahead of the limiter (`audio.limiter`).
+    /// The renderer adds its own (unknown) buffering on top of this.
+    pub fn expected_latency(&self, capture_buffer: Duration, limiter: bool) -> Duration {
+        let lookahead = if limiter { LOOKAHEAD } else { Duration::ZERO };
+        let buffers = self.buffer_ms + self.chunk_ms + self.queue_ms;
+        capture_buffer + lookahead + Duration::from_millis(buffers as u64)
+    }
+}
+
+impl fmt::Display for LatencyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LatencyMode::Low => write!(f, "low"),
+            LatencyMode::Balanced => write!(f, "balanced"),
+            LatencyMode::Safe => write!(f, "safe"),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+pub mod buffer;
+pub mod drift;
+pub mod latency;
+pub mod preroll;
+pub mod renderer_clock;
+pub mod sync;
+
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+use serde::Serialize;
+
+/// statistics of a connected client
+///
+/// updated by the client's streaming thread, read by the stats api
+#[derive(Debug, Default)]
+pub struct ClientStats {
+    /// samples dropped because the client could not keep up
+    pub dropped_samples: AtomicU64,
+    /// number of times the buffer ran empty and had to be refilled
+    pub underruns: AtomicU64,
+    /// number of times the buffer overflowed and old samples were dropped
+    pub overruns: AtomicU64,
+    /// samples currently waiting in the client's buffer
+    pub buffered_samples: AtomicU64,
+    /// samples sent to the client
+    pub sent_samples: AtomicU64,
+    /// synthetic silence samples sent while the capture was quiet
+    pub silence_samples: AtomicU64,
+    /// estimated clock drift between capture device and renderer in ppm
+    pub drift_ppm: AtomicI64,
+    /// how much faster the renderer plays than the host clock in ppm
+    pub renderer_drift_ppm: AtomicI64,
+    /// number of times the limiter started reducing the gain
+    pub limiter_events: AtomicU64,
+    /// ring position of the next sample sent, to compare multiroom members
+    pub position: AtomicU64,
+}
+
+/// a point in time copy of [ClientStats]
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsSnapshot {
+    pub dropped_samples: u64,
+    pub underruns: u64,
+    pub overruns: u64,
+    pub buffered_samples: u64,
+    pub sent_samples: u64,
+    pub silence_samples: u64,
+    pub drift_ppm: i64,
+    pub renderer_drift_ppm: i64,
+    pub limiter_events: u64,
+    pub position: u64,
+}
+
+impl ClientStats {
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            dropped_samples: self.dropped_samples.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+            overruns: self.overruns.load(Ordering::Relaxed),
+            buffered_samples: self.buffered_samples.load(Ordering::Relaxed),
+            sent_samples: self.sent_samples.load(Ordering::Relaxed),
+            silence_samples: self.silence_samples.load(Ordering::Relaxed),
+            drift_ppm: self.drift_ppm.load(Ordering::Relaxed),
+            renderer_drift_ppm: self.renderer_drift_ppm.load(Ordering::Relaxed),
+            limiter_events: self.limiter_events.load(Ordering::Relaxed),
+            position: self.position.load(Ordering::Relaxed),
+        }
+    }
+}
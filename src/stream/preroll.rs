@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::ring::{ReadResult, RingReader, SampleRing};
+
+/// what is sent in a burst to a renderer right after it connected
+///
+/// renderers like Sonos buffer several seconds before they start playing,
+/// a pre-roll fills that buffer immediately so playback starts sooner
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrerollMode {
+    #[default]
+    Off,
+    /// the most recently captured audio
+    History,
+    /// silence
+    Silence,
+}
+
+/// Preroll - the samples sent in a burst before the paced stream starts
+pub struct Preroll<'a> {
+    history: Option<RingReader<'a>>,
+    remaining: u64,
+}
+
+impl<'a> Preroll<'a> {
+    /// Creates a pre-roll of `ms` milliseconds that ends at the ring position `end`.
+    ///
+    /// Falls back to silence if no audio was captured within `max_age`,
+    /// there is no point in replaying stale audio.
+    pub fn new(
+        ring: &'a SampleRing,
+        mode: PrerollMode,
+        ms: usize,
+        end: u64,
+        max_age: Duration,
+    ) -> Self {
+        let samples = ring.ms_to_samples(ms);
+        match mode {
+            PrerollMode::Off => Self {
+                history: None,
+                remaining: 0,
+            },
+            PrerollMode::History if ring.since_last_write() <= max_age => {
+                let reader = ring.reader_before(end, samples);
+                Self {
+                    remaining: end.saturating_sub(reader.position()),
+                    history: Some(reader),
+                }
+            }
+            PrerollMode::History | PrerollMode::Silence => Self {
+                history: None,
+                remaining: samples,
+            },
+        }
+    }
+
+    /// Fills `out` with the next pre-roll samples.
+    ///
+    /// Returns the number of samples written, `0` once the pre-roll is complete.
+    pub fn read(&mut self, out: &mut [f32]) -> usize {
+        let n = out.len().min(self.remaining as usize);
+        let n = match &mut self.history {
+            Some(reader) => match reader.read(&mut out[..n]) {
+                ReadResult::Samples(n) => n,
+                ReadResult::Overrun(_) => 0,
+            },
+            None => {
+                out[..n].fill(0.0);
+                n
+            }
+        };
+        self.remaining = if n == 0 { 0 } else { self.remaining - n as u64 };
+        n
+    }
+}
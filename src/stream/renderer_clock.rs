@@ -0,0 +1,155 @@
+use std::net::TcpStream;
+
+use super::drift::DriftEstimator;
+
+/// the largest drift between the host clock and a renderer that is followed (1000 ppm),
+/// below the capture's limit so the resampler can follow both at once
+const MAX_DRIFT: f64 = 0.001;
+/// the largest correction of the sending pace (1500 ppm)
+const MAX_CORRECTION: f64 = 0.0015;
+
+/// RendererClock - follows the playback clock of a renderer through TCP backpressure
+///
+/// A renderer reads the stream only as fast as it plays it, once its own buffer
+/// is full. Audio sent faster than that piles up in the socket's send queue,
+/// audio sent slower lets the queue run empty. The pace of sending is adjusted
+/// to keep `target` frames queued, which makes it match the renderer's clock.
+/// The client's [JitterBuffer](super::buffer::JitterBuffer) is then drained at
+/// the renderer's pace, so its drift estimate, and the resampling, covers the
+/// drift between the capture device and the renderer.
+///
+/// Until the renderer's buffer is full the queue stays empty and sending runs
+/// slightly ahead of the host clock. Where the send queue can't be read, e.g. on
+/// Windows, the stream follows the host clock.
+pub struct RendererClock {
+    estimator: DriftEstimator,
+    bytes_per_frame: usize,
+}
+
+impl RendererClock {
+    pub fn new(target: u64, bytes_per_frame: usize) -> Self {
+        Self {
+            estimator: DriftEstimator::with_limits(target, MAX_DRIFT, MAX_CORRECTION),
+            bytes_per_frame: bytes_per_frame.max(1),
+        }
+    }
+
+    /// Measures the send queue of the stream and returns the factor the sending
+    /// period is scaled with, above 1.0 while the renderer plays slower than the host.
+    pub fn update(&mut self, stream: &TcpStream) -> f64 {
+        match queued_bytes(stream) {
+            Some(bytes) => self.update_queue((bytes / self.bytes_per_frame) as u64),
+            None => 1.0,
+        }
+    }
+
+    /// feeds the number of queued frames, see [RendererClock::update]
+    fn update_queue(&mut self, queued: u64) -> f64 {
+        self.estimator.update(queued)
+    }
+
+    /// Forgets the measured queue, e.g. after sending paused for a refill.
+    ///
+    /// The drift estimate is kept.
+    pub fn reset(&mut self) {
+        self.estimator.reset();
+    }
+
+    /// how much faster the renderer plays than the host clock in parts per million
+    pub fn drift_ppm(&self) -> f64 {
+        -self.estimator.drift_ppm()
+    }
+}
+
+/// bytes written to the socket that the renderer hasn't acknowledged yet
+#[cfg(target_os = "linux")]
+fn queued_bytes(stream: &TcpStream) -> Option<usize> {
+    use std::os::fd::AsRawFd;
+    let mut bytes: libc::c_int = 0;
+    // TIOCOUTQ is SIOCOUTQ for sockets: unsent and unacknowledged bytes
+    let rc = unsafe { libc::ioctl(stream.as_raw_fd(), libc::TIOCOUTQ, &mut bytes) };
+    (rc == 0).then_some(bytes.max(0) as usize)
+}
+
+/// bytes written to the socket that the renderer hasn't acknowledged yet
+#[cfg(target_os = "macos")]
+fn queued_bytes(stream: &TcpStream) -> Option<usize> {
+    use std::os::fd::AsRawFd;
+    let mut bytes: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_NWRITE,
+            &mut bytes as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (rc == 0).then_some(bytes.max(0) as usize)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn queued_bytes(_stream: &TcpStream) -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 10ms chunks at 48kHz
+    const CHUNK: f64 = 480.0;
+    /// 40ms of queued audio
+    const TARGET: u64 = 1920;
+    /// the renderer buffers 1s and reads in blocks of 4096 frames
+    const RENDERER_BUFFER: f64 = 48000.0;
+    const READ: f64 = 4096.0;
+
+    /// A renderer playing `drift` faster than the host clock, whose buffer
+    /// starts at `buffered` frames. Returns the clock, the highest queue after
+    /// the first 500s and whether the renderer ran dry.
+    fn run(drift: f64, buffered: f64, chunks: usize) -> (RendererClock, f64, bool) {
+        let mut clock = RendererClock::new(TARGET, 4);
+        let (mut queue, mut buffer, mut ratio) = (0.0, buffered, 1.0);
+        let (mut highest, mut dry) = (0f64, false);
+        for chunk in 0..chunks {
+            queue += CHUNK;
+            // sending the chunk took `ratio` periods of the host clock
+            let played = CHUNK * ratio * (1.0 + drift);
+            dry |= buffer < played;
+            buffer = (buffer - played).max(0.0);
+            let space = ((RENDERER_BUFFER - buffer) / READ).floor() * READ;
+            let read = space.min(queue);
+            queue -= read;
+            buffer += read;
+            ratio = clock.update_queue(queue as u64);
+            if chunk > 50_000 {
+                highest = highest.max(queue);
+            }
+        }
+        (clock, highest, dry)
+    }
+
+    #[test]
+    fn follows_the_renderer_clock() {
+        let runs = [
+            (100.0, RENDERER_BUFFER),
+            (-100.0, RENDERER_BUFFER),
+            (300.0, RENDERER_BUFFER / 2.0),
+        ];
+        for (ppm, buffered) in runs {
+            let (clock, highest, dry) = run(ppm / 1e6, buffered, 200_000);
+            let estimate = clock.drift_ppm();
+            assert!((estimate - ppm).abs() < 5.0, "{estimate} ppm for {ppm} ppm");
+            assert!(highest < 3.0 * TARGET as f64, "{highest} frames queued");
+            assert!(!dry);
+        }
+    }
+
+    #[test]
+    fn sends_ahead_until_the_renderer_buffer_is_full() {
+        let (clock, ..) = run(0.0, RENDERER_BUFFER / 2.0, 10_000);
+        assert_eq!(clock.drift_ppm(), MAX_DRIFT * 1e6);
+    }
+}
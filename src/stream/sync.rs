@@ -0,0 +1,179 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::audio::ring::SampleRing;
+
+use super::ClientStats;
+
+/// SyncGroup - lines up the streams of the renderers of a multiroom group
+///
+/// the first member to connect waits until all members are connected (or the
+/// timeout passed), then all of them start reading the ring at the same
+/// position, a member that connects later starts at the group's current
+/// position. since every member keeps the same fill level afterwards and sends
+/// at the pace of its renderer's clock, the renderers play the same audio at
+/// the same time
+#[derive(Default)]
+pub struct SyncGroup {
+    state: Mutex<GroupState>,
+    started: Condvar,
+}
+
+#[derive(Default)]
+struct GroupState {
+    /// ring position the members start at, `None` until the group started
+    start: Option<u64>,
+    /// the members that connected since the group was (re)started
+    joined: HashSet<IpAddr>,
+    /// open streams of all members
+    connections: usize,
+}
+
+impl SyncGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Joins the group and waits for the other members.
+    ///
+    /// Returns the ring position to start at: the common start position, or
+    /// `current()` if the group was already streaming when this member connected.
+    pub fn join(
+        &self,
+        ip: IpAddr,
+        members: &[IpAddr],
+        ring: &SampleRing,
+        target: u64,
+        timeout: Duration,
+        current: impl FnOnce() -> Option<u64>,
+    ) -> u64 {
+        let mut state = self.state.lock();
+        state.connections += 1;
+        state.joined.insert(ip);
+        if state.start.is_some() {
+            return current().unwrap_or_else(|| ring.write_pos().saturating_sub(target));
+        }
+
+        let deadline = Instant::now() + timeout;
+        while state.start.is_none() && !members.iter().all(|m| state.joined.contains(m)) {
+            if self.started.wait_until(&mut state, deadline).timed_out() {
+                break;
+            }
+        }
+        match state.start {
+            Some(start) => start,
+            None => {
+                let start = ring.write_pos().saturating_sub(target);
+                state.start = Some(start);
+                self.started.notify_all();
+                start
+            }
+        }
+    }
+
+    /// leave the group when the stream ends
+    pub fn leave(&self) {
+        let mut state = self.state.lock();
+        state.connections = state.connections.saturating_sub(1);
+        if state.connections == 0 {
+            state.start = None;
+            state.joined.clear();
+        }
+    }
+
+    /// let the members that connect next start together again
+    pub fn reset(&self) {
+        let mut state = self.state.lock();
+        state.start = None;
+        state.joined.clear();
+    }
+}
+
+/// mean_position - the mean ring position the streaming members of a group send from
+///
+/// members that haven't sent anything yet are left out, `None` if none is streaming
+pub fn mean_position(members: &[IpAddr], clients: &HashMap<IpAddr, Arc<ClientStats>>) -> Option<f64> {
+    let positions: Vec<u64> = members
+        .iter()
+        .filter_map(|ip| clients.get(ip))
+        .filter(|stats| stats.sent_samples.load(Ordering::Relaxed) > 0)
+        .map(|stats| stats.position.load(Ordering::Relaxed))
+        .collect();
+    (!positions.is_empty()).then(|| positions.iter().sum::<u64>() as f64 / positions.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    const TARGET: u64 = 1000;
+
+    fn ring() -> SampleRing {
+        let ring = SampleRing::new(1 << 16);
+        ring.push([0.0; 5000]);
+        ring
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    #[test]
+    fn members_start_together() {
+        let (group, ring) = (SyncGroup::new(), ring());
+        let members = [ip(1), ip(2)];
+        let timeout = Duration::from_secs(5);
+        let (group, ring) = (&group, &ring);
+        let starts: Vec<u64> = thread::scope(|scope| {
+            let joins: Vec<_> = members
+                .iter()
+                .map(|&member| scope.spawn(move || group.join(member, &members, ring, TARGET, timeout, || None)))
+                .collect();
+            joins.into_iter().map(|join| join.join().unwrap()).collect()
+        });
+        assert_eq!(starts, [4000, 4000]);
+    }
+
+    #[test]
+    fn late_member_starts_at_the_group_position() {
+        let (group, ring) = (SyncGroup::new(), ring());
+        let members = [ip(1), ip(2)];
+        let timeout = Duration::from_millis(10);
+        // the first member stops waiting for the second one
+        assert_eq!(group.join(ip(1), &members, &ring, TARGET, timeout, || None), 4000);
+
+        ring.push([0.0; 2000]);
+        assert_eq!(group.join(ip(2), &members, &ring, TARGET, timeout, || Some(5500)), 5500);
+        // nobody else is streaming
+        assert_eq!(group.join(ip(3), &members, &ring, TARGET, timeout, || None), 6000);
+    }
+
+    #[test]
+    fn mean_position_of_streaming_members() {
+        let stats = |sent: u64, position: u64| {
+            let stats = ClientStats::default();
+            stats.sent_samples.store(sent, Ordering::Relaxed);
+            stats.position.store(position, Ordering::Relaxed);
+            Arc::new(stats)
+        };
+        let mut clients = HashMap::new();
+        clients.insert(ip(1), stats(100, 4000));
+        clients.insert(ip(2), stats(100, 4100));
+        // connected, but hasn't sent anything yet
+        clients.insert(ip(3), stats(0, 0));
+        // not a member
+        clients.insert(ip(4), stats(100, 9000));
+
+        let members = [ip(1), ip(2), ip(3), ip(5)];
+        assert_eq!(mean_position(&members, &clients), Some(4050.0));
+        assert_eq!(mean_position(&[ip(3)], &clients), None);
+    }
+}